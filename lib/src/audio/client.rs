@@ -7,17 +7,18 @@ use super::analyze;
 use super::ringbuf::{self, Consumer, Producer, RingBuffer};
 use super::{Frame, FFT, FFT_SIZE, FRAME_SIZE};
 
-use super::midi::{Midi, MidiState, MidiBank, MidiRaw};
+use crate::midi::message::{self, MidiRaw};
 
 const FRAME_QUEUE_SIZE: usize = 64;
 const FFT_QUEUE_SIZE: usize = 16;
+const MIDI_QUEUE_SIZE: usize = 128;
 
 pub struct Jack {
     pub samples: Frame,
     pub fft: FFT,
-    midi: MidiState,
 
-    midi_rx: Arc<ArrayQueue<MidiRaw>>,
+    midi_rx: Arc<ArrayQueue<(u64, MidiRaw)>>,
+    midi_tx: Arc<ArrayQueue<Vec<u8>>>,
     samples_rx: Consumer<Frame>,
     fft_rx: Consumer<FFT>,
 }
@@ -33,14 +34,9 @@ impl Jack {
         }
     }
 
-    pub fn midi(&mut self) -> Vec<(MidiBank, Midi)> {
-        let mut messages = Vec::new();
-
-        while let Some(raw) = self.midi_rx.pop() {
-            messages.push(self.midi.process(raw));
-        }
-
-        messages
+    // Queues to and from the JACK MIDI ports, picked up by midi::Jack
+    pub(crate) fn midi_queues(&self) -> (Arc<ArrayQueue<(u64, MidiRaw)>>, Arc<ArrayQueue<Vec<u8>>>) {
+        (Arc::clone(&self.midi_rx), Arc::clone(&self.midi_tx))
     }
 
     pub fn rms(&self) -> f32 {
@@ -74,10 +70,17 @@ impl Default for Jack {
         let in_midi = client
             .register_port("midi", jack::MidiIn::default())
             .unwrap();
+        let mut out_midi = client
+            .register_port("midi_out", jack::MidiOut::default())
+            .unwrap();
+
+        // Create a queue for receiving MIDI messages
+        let midi_rx = Arc::new(ArrayQueue::<(u64, MidiRaw)>::new(MIDI_QUEUE_SIZE));
+        let midi_in_tx = Arc::clone(&midi_rx);
 
-        // // Create a queue for sending MIDI messages
-        let midi_rx = Arc::new(ArrayQueue::<MidiRaw>::new(128));
-        let midi_tx = Arc::clone(&midi_rx);
+        // Create a queue for sending MIDI messages
+        let midi_tx = Arc::new(ArrayQueue::<Vec<u8>>::new(MIDI_QUEUE_SIZE));
+        let midi_out_rx = Arc::clone(&midi_tx);
 
         // Create a ringbuffer for sending raw samples from the JACK processing thread to the analysis thread
         let jack_analyze_buffer = RingBuffer::<Frame>::new(FRAME_QUEUE_SIZE);
@@ -102,7 +105,9 @@ impl Default for Jack {
                         &in_left,
                         &in_right,
                         &in_midi,
-                        &midi_tx,
+                        &mut out_midi,
+                        &midi_in_tx,
+                        &midi_out_rx,
                         &mut process_buffer,
                         &mut jack_analyze_tx,
                         &mut jack_main_tx,
@@ -122,8 +127,8 @@ impl Default for Jack {
         thread::spawn(move || analyze::analyze(jack_analyze_rx, fft_tx));
 
         Self {
-            midi: MidiState::default(),
             midi_rx,
+            midi_tx,
             fft_rx,
             samples: [0.0; FRAME_SIZE],
             samples_rx: jack_main_rx,
@@ -133,12 +138,14 @@ impl Default for Jack {
 }
 
 pub fn process(
-    _j: &jack::Client,
+    j: &jack::Client,
     ps: &jack::ProcessScope,
     in_left: &jack::Port<jack::AudioIn>,
     in_right: &jack::Port<jack::AudioIn>,
     in_midi: &jack::Port<jack::MidiIn>,
-    midi_tx: &Arc<ArrayQueue<MidiRaw>>,
+    out_midi: &mut jack::Port<jack::MidiOut>,
+    midi_tx: &Arc<ArrayQueue<(u64, MidiRaw)>>,
+    midi_rx: &Arc<ArrayQueue<Vec<u8>>>,
     buffer: &mut Frame,
     analyze_tx: &mut Producer<Frame>,
     main_tx: &mut Producer<Frame>,
@@ -157,14 +164,22 @@ pub fn process(
 
     in_midi.iter(ps).for_each(|m| {
         if !midi_tx.is_full() {
-            let mut buf = [0; 16];
-            for (i, b) in m.bytes.iter().take(16).enumerate() {
-                buf[i] = *b;
-            }
-            midi_tx.push(buf).unwrap();
+            let buf = message::raw_from(m.bytes);
+            // Timestamp in microseconds, accurate to the frame within the period
+            let stamp = j.frames_to_time(ps.last_frame_time() + m.time);
+            midi_tx.push((stamp, buf)).unwrap();
         }
     });
 
+    let mut writer = out_midi.writer(ps);
+    while let Some(bytes) = midi_rx.pop() {
+        let raw = jack::RawMidi { time: 0, bytes: &bytes };
+        if writer.write(&raw).is_err() {
+            log::trace!("JACK: MIDI output buffer full");
+            break;
+        }
+    }
+
     ringbuf::transmit(analyze_tx, buffer);
     ringbuf::transmit(main_tx, buffer);
 
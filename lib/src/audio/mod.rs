@@ -8,10 +8,8 @@ const FFT_IMSIZE: usize = FFT_SIZE * 2;
 pub type FFT = [f32; FFT_SIZE];
 
 mod client;
-mod midi;
 mod analyze;
 mod ringbuf;
 
 pub use client::Jack as Audio;
 pub use analyze::prelude::*;
-pub use midi::{MidiBank, Midi};
\ No newline at end of file
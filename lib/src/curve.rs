@@ -0,0 +1,548 @@
+use serde::{Deserialize, Serialize};
+
+use std::f32::consts::PI;
+use std::path::Path;
+
+use splines::{Interpolation, Key as SplineKey, Spline};
+
+use crate::time::BeatClock;
+use crate::timeline::Unit;
+
+// Maps progress or a control position in [0.0, 1.0] onto [0.0, 1.0]. Shared by automation
+// keys, envelope segments, parameter tapers and modulation routes. The standard easings are
+// from easings.net
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Ease {
+    Linear,
+    InQuad,
+    OutQuad,
+    InOutQuad,
+    InCubic,
+    OutCubic,
+    InOutCubic,
+    InSine,
+    OutSine,
+    InOutSine,
+    InExpo,
+    OutExpo,
+    InOutExpo,
+    // Overshoots below 0.0 or above 1.0
+    InBack,
+    OutBack,
+    InOutBack,
+    InBounce,
+    OutBounce,
+    Smoothstep,
+    // Audio taper, fine control at the bottom of the range
+    Exp,
+    // Inverse audio taper, fine control at the top of the range
+    Log,
+    Pow(f32),
+    // Off below half way, on above
+    Toggle,
+    // Slow at first then rushing in. Higher is more curved
+    InExp(f32),
+    // Fast at first then easing in, like a capacitor charging
+    OutExp(f32),
+    // Can't be saved
+    #[serde(skip)]
+    Custom(fn(f32) -> f32),
+}
+
+impl Ease {
+    // Map progress in [0.0, 1.0] onto [0.0, 1.0], clamping the input
+    pub fn apply(&self, f: f32) -> f32 {
+        const C1: f32 = 1.70158;
+        const C2: f32 = C1 * 1.525;
+        const C3: f32 = C1 + 1.0;
+
+        let f = f.max(0.0).min(1.0);
+        match *self {
+            Ease::Linear => f,
+            Ease::InQuad => f * f,
+            Ease::OutQuad => f * (2.0 - f),
+            Ease::InOutQuad => {
+                if f < 0.5 {
+                    2.0 * f * f
+                } else {
+                    1.0 - (2.0 - 2.0 * f).powi(2) / 2.0
+                }
+            }
+            Ease::InCubic => f * f * f,
+            Ease::OutCubic => 1.0 - (1.0 - f).powi(3),
+            Ease::InOutCubic => {
+                if f < 0.5 {
+                    4.0 * f * f * f
+                } else {
+                    1.0 - (2.0 - 2.0 * f).powi(3) / 2.0
+                }
+            }
+            Ease::InSine => 1.0 - (f * PI / 2.0).cos(),
+            Ease::OutSine => (f * PI / 2.0).sin(),
+            Ease::InOutSine => (1.0 - (f * PI).cos()) / 2.0,
+            Ease::InExpo if f == 0.0 => 0.0,
+            Ease::InExpo => (10.0 * f - 10.0).exp2(),
+            Ease::OutExpo if f == 1.0 => 1.0,
+            Ease::OutExpo => 1.0 - (-10.0 * f).exp2(),
+            Ease::InOutExpo if f == 0.0 || f == 1.0 => f,
+            Ease::InOutExpo => {
+                if f < 0.5 {
+                    (20.0 * f - 10.0).exp2() / 2.0
+                } else {
+                    (2.0 - (10.0 - 20.0 * f).exp2()) / 2.0
+                }
+            }
+            Ease::InBack => C3 * f * f * f - C1 * f * f,
+            Ease::OutBack => 1.0 + C3 * (f - 1.0).powi(3) + C1 * (f - 1.0).powi(2),
+            Ease::InOutBack => {
+                if f < 0.5 {
+                    (2.0 * f).powi(2) * ((C2 + 1.0) * 2.0 * f - C2) / 2.0
+                } else {
+                    ((2.0 * f - 2.0).powi(2) * ((C2 + 1.0) * (2.0 * f - 2.0) + C2) + 2.0) / 2.0
+                }
+            }
+            Ease::InBounce => 1.0 - bounce(1.0 - f),
+            Ease::OutBounce => bounce(f),
+            Ease::Smoothstep => f * f * (3.0 - 2.0 * f),
+            Ease::Exp => ((10.0 * f).exp2() - 1.0) / 1023.0,
+            Ease::Log => (1.0 + 1023.0 * f).log2() / 10.0,
+            Ease::Pow(p) => f.powf(p),
+            Ease::Toggle => {
+                if f >= 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Ease::InExp(k) if k > 0.0 => 1.0 - Ease::OutExp(k).apply(1.0 - f),
+            Ease::OutExp(k) if k > 0.0 => (1.0 - (-k * f).exp()) / (1.0 - (-k).exp()),
+            Ease::InExp(_) | Ease::OutExp(_) => f,
+            Ease::Custom(c) => c(f),
+        }
+    }
+
+    // Recover the progress that would produce `v`. Curves that overshoot or bounce give one
+    // of the candidates
+    pub fn invert(&self, v: f32) -> f32 {
+        let v = v.max(0.0).min(1.0);
+        match *self {
+            Ease::Linear | Ease::Toggle => v,
+            Ease::Exp => (1.0 + 1023.0 * v).log2() / 10.0,
+            Ease::Log => ((10.0 * v).exp2() - 1.0) / 1023.0,
+            Ease::Pow(p) => v.powf(1.0 / p),
+            Ease::InQuad => v.sqrt(),
+            Ease::InCubic => v.cbrt(),
+            _ => {
+                // Bisect, the curves all start at 0.0 and end at 1.0
+                let (mut lo, mut hi) = (0.0, 1.0);
+                for _ in 0..24 {
+                    let mid = (lo + hi) / 2.0;
+                    if self.apply(mid) < v {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                (lo + hi) / 2.0
+            }
+        }
+    }
+}
+
+impl PartialEq for Ease {
+    // Custom curves are equal when they're the same function
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Ease::Custom(a), Ease::Custom(b)) => *a as usize == *b as usize,
+            (Ease::Pow(a), Ease::Pow(b))
+            | (Ease::InExp(a), Ease::InExp(b))
+            | (Ease::OutExp(a), Ease::OutExp(b)) => a == b,
+            _ => std::mem::discriminant(self) == std::mem::discriminant(other),
+        }
+    }
+}
+
+impl Default for Ease {
+    fn default() -> Self {
+        Ease::Linear
+    }
+}
+
+fn bounce(f: f32) -> f32 {
+    const N: f32 = 7.5625;
+    const D: f32 = 2.75;
+
+    if f < 1.0 / D {
+        N * f * f
+    } else if f < 2.0 / D {
+        let f = f - 1.5 / D;
+        N * f * f + 0.75
+    } else if f < 2.5 / D {
+        let f = f - 2.25 / D;
+        N * f * f + 0.9375
+    } else {
+        let f = f - 2.625 / D;
+        N * f * f + 0.984375
+    }
+}
+
+// How a key moves on to the next one
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Interp {
+    // Hold until the next key
+    Step,
+    Ease(Ease),
+    // Smooth through the neighbouring keys
+    CatmullRom,
+}
+
+impl Default for Interp {
+    fn default() -> Self {
+        Interp::Ease(Ease::Linear)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Key {
+    pub t: f32,
+    pub v: f32,
+    #[serde(default)]
+    pub interp: Interp,
+}
+
+// What happens past the last key
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Repeat {
+    // Hold the ends
+    Clamp,
+    Loop,
+    // Back and forth
+    PingPong,
+}
+
+impl Repeat {
+    // Fold a time into [start, end]
+    pub fn apply(&self, t: f32, start: f32, end: f32) -> f32 {
+        let len = end - start;
+        if len <= 0.0 {
+            return start;
+        }
+
+        match self {
+            Repeat::Clamp => t.max(start).min(end),
+            Repeat::Loop => start + (t - start).rem_euclid(len),
+            Repeat::PingPong => {
+                let f = (t - start).rem_euclid(2.0 * len);
+                start + if f > len { 2.0 * len - f } else { f }
+            }
+        }
+    }
+}
+
+// Keyframed automation of a single value, against seconds or beats, saved as JSON
+#[derive(Serialize, Deserialize)]
+pub struct Automation {
+    pub unit: Unit,
+    pub repeat: Repeat,
+    keys: Vec<Key>,
+    #[serde(skip)]
+    pos: f32,
+    // The keys with padding at each end, for Catmull-Rom segments
+    #[serde(skip)]
+    spline: Option<Spline<f32, f32>>,
+}
+
+impl Automation {
+    pub fn new(unit: Unit, repeat: Repeat) -> Self {
+        Self {
+            unit,
+            repeat,
+            keys: Vec::new(),
+            pos: 0.0,
+            spline: None,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let mut automation: Self = serde_json::from_slice(&data)?;
+
+        // Hand edited files can be out of order, insert sorts them and keeps the last
+        // key at any one time
+        let keys = std::mem::take(&mut automation.keys);
+        for key in keys {
+            if key.t.is_finite() {
+                automation.insert(key);
+            } else {
+                log::warn!("Dropping automation key at {}", key.t);
+            }
+        }
+
+        automation.build();
+        Ok(automation)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data)
+    }
+
+    pub fn key(mut self, t: f32, v: f32, interp: Interp) -> Self {
+        self.insert(Key { t, v, interp });
+        self
+    }
+
+    // Replaces any key at the same time
+    pub fn insert(&mut self, key: Key) {
+        match self.keys.iter().position(|k| k.t >= key.t) {
+            Some(i) if self.keys[i].t == key.t => self.keys[i] = key,
+            Some(i) => self.keys.insert(i, key),
+            None => self.keys.push(key),
+        }
+        self.build();
+    }
+
+    pub fn remove(&mut self, i: usize) -> Option<Key> {
+        if i < self.keys.len() {
+            let key = self.keys.remove(i);
+            self.build();
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    pub fn keys(&self) -> &[Key] {
+        &self.keys
+    }
+
+    pub fn start(&self) -> f32 {
+        self.keys.first().map_or(0.0, |k| k.t)
+    }
+
+    pub fn end(&self) -> f32 {
+        self.keys.last().map_or(0.0, |k| k.t)
+    }
+
+    // Value at a time in the automation's unit, holding the ends. 0.0 without keys
+    pub fn sample(&self, t: f32) -> f32 {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+
+        if t <= first.t {
+            return first.v;
+        }
+        if t >= last.t {
+            return last.v;
+        }
+
+        // First key after t, there's always one before it
+        let i = self.keys.iter().position(|k| k.t > t).unwrap_or(self.keys.len() - 1);
+        let (k0, k1) = (&self.keys[i - 1], &self.keys[i]);
+        let f = (t - k0.t) / (k1.t - k0.t);
+
+        match k0.interp {
+            Interp::Step => k0.v,
+            Interp::Ease(ease) => k0.v + (k1.v - k0.v) * ease.apply(f),
+            Interp::CatmullRom => self
+                .spline
+                .as_ref()
+                .and_then(|s| s.sample(t))
+                .unwrap_or(k0.v + (k1.v - k0.v) * f),
+        }
+    }
+
+    // Value at a time in the automation's unit, past the ends as set by `repeat`
+    pub fn at(&self, t: f32) -> f32 {
+        self.sample(self.repeat.apply(t, self.start(), self.end()))
+    }
+
+    // Value at a time in seconds, converted with `bpm` for beat automation
+    pub fn at_seconds(&self, t: f32, bpm: f32) -> f32 {
+        match self.unit {
+            Unit::Seconds => self.at(t),
+            Unit::Beats => self.at(t * bpm / 60.0),
+        }
+    }
+
+    pub fn pos(&self) -> f32 {
+        self.pos
+    }
+
+    pub fn seek(&mut self, pos: f32) {
+        self.pos = pos;
+    }
+
+    pub fn restart(&mut self) {
+        self.pos = self.start();
+    }
+
+    // Advance playback, beat automation follows the clock's tempo
+    pub fn update(&mut self, delta: f32, clock: &BeatClock) {
+        self.pos += match self.unit {
+            Unit::Seconds => delta,
+            Unit::Beats => delta * clock.bpm / 60.0,
+        };
+
+        // Keep the position small so long sets don't lose precision
+        if self.repeat != Repeat::Clamp {
+            let len = self.end() - self.start();
+            if len > 0.0 {
+                let period = if self.repeat == Repeat::PingPong { 2.0 * len } else { len };
+                self.pos = self.start() + (self.pos - self.start()).rem_euclid(period);
+            }
+        }
+    }
+
+    // Value at the playback position
+    pub fn v(&self) -> f32 {
+        self.at(self.pos)
+    }
+
+    fn build(&mut self) {
+        let n = self.keys.len();
+        if n < 2 {
+            self.spline = None;
+            return;
+        }
+
+        // Repeat the end keys as far out as their neighbours so every segment has a
+        // key either side
+        let (first, last) = (self.keys[0], self.keys[n - 1]);
+        let mut keys = Vec::with_capacity(n + 2);
+        keys.push(SplineKey::new(2.0 * first.t - self.keys[1].t, first.v, Interpolation::Linear));
+        keys.extend(self.keys.iter().map(|k| SplineKey::new(k.t, k.v, Interpolation::CatmullRom)));
+        keys.push(SplineKey::new(2.0 * last.t - self.keys[n - 2].t, last.v, Interpolation::Linear));
+
+        self.spline = Some(Spline::from_vec(keys));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LINEAR: Interp = Interp::Ease(Ease::Linear);
+
+    #[test]
+    fn eases_invert() {
+        let eases = [
+            Ease::Linear,
+            Ease::InQuad,
+            Ease::InOutCubic,
+            Ease::OutSine,
+            Ease::InOutExpo,
+            Ease::Smoothstep,
+            Ease::Exp,
+            Ease::Log,
+            Ease::Pow(0.5),
+            Ease::InExp(4.0),
+            Ease::OutExp(4.0),
+        ];
+
+        for ease in eases.iter() {
+            assert!(ease.apply(0.0).abs() < 1e-6, "{:?}", ease);
+            assert!((ease.apply(1.0) - 1.0).abs() < 1e-6, "{:?}", ease);
+            for &f in [0.1, 0.25, 0.5, 0.9].iter() {
+                assert!((ease.invert(ease.apply(f)) - f).abs() < 1e-3, "{:?} at {}", ease, f);
+            }
+        }
+
+        // Envelope segments mirror each other
+        assert!((Ease::InExp(3.0).apply(0.2) + Ease::OutExp(3.0).apply(0.8) - 1.0).abs() < 1e-6);
+        assert_eq!(Ease::Toggle.apply(0.49), 0.0);
+        assert_eq!(Ease::Custom(|f| f * f).apply(2.0), 1.0);
+    }
+
+    #[test]
+    fn clamps_ends() {
+        let empty = Automation::new(Unit::Seconds, Repeat::Clamp);
+        assert_eq!(empty.sample(1.0), 0.0);
+
+        let single = Automation::new(Unit::Seconds, Repeat::Loop).key(1.0, 5.0, LINEAR);
+        assert_eq!(single.sample(0.0), 5.0);
+        assert_eq!(single.at(3.0), 5.0);
+
+        let a = Automation::new(Unit::Seconds, Repeat::Clamp)
+            .key(1.0, 2.0, LINEAR)
+            .key(3.0, 4.0, LINEAR);
+        assert_eq!(a.sample(0.0), 2.0);
+        assert_eq!(a.sample(2.0), 3.0);
+        assert_eq!(a.sample(5.0), 4.0);
+        assert_eq!(a.at(-10.0), 2.0);
+        assert_eq!(a.at(10.0), 4.0);
+    }
+
+    #[test]
+    fn catmull_rom_reaches_ends() {
+        let a = Automation::new(Unit::Seconds, Repeat::Clamp)
+            .key(0.0, 0.0, Interp::CatmullRom)
+            .key(1.0, 1.0, Interp::CatmullRom)
+            .key(2.0, 0.0, Interp::CatmullRom);
+
+        assert_eq!(a.sample(0.0), 0.0);
+        assert_eq!(a.sample(2.0), 0.0);
+        assert!((a.sample(1.0) - 1.0).abs() < 1e-4);
+        assert!(a.sample(1e-3).abs() < 1e-2);
+        assert!(a.sample(2.0 - 1e-3).abs() < 1e-2);
+    }
+
+    #[test]
+    fn loops() {
+        let a = Automation::new(Unit::Seconds, Repeat::Loop)
+            .key(0.0, 0.0, LINEAR)
+            .key(2.0, 2.0, LINEAR);
+        assert_eq!(a.at(1.0), 1.0);
+        assert_eq!(a.at(3.0), 1.0);
+        assert_eq!(a.at(-1.0), 1.0);
+
+        let a = Automation::new(Unit::Seconds, Repeat::PingPong)
+            .key(0.0, 0.0, LINEAR)
+            .key(2.0, 2.0, LINEAR);
+        assert_eq!(a.at(1.5), 1.5);
+        assert_eq!(a.at(2.5), 1.5);
+        assert_eq!(a.at(5.0), 1.0);
+    }
+
+    #[test]
+    fn update_follows_beats() {
+        let clock = BeatClock::new(120.0);
+        let mut a = Automation::new(Unit::Beats, Repeat::Loop)
+            .key(0.0, 0.0, LINEAR)
+            .key(2.0, 2.0, LINEAR);
+
+        // Half a second is a beat at 120 BPM
+        a.update(0.5, &clock);
+        assert_eq!(a.v(), 1.0);
+
+        a.update(0.5, &clock);
+        a.update(0.5, &clock);
+        assert_eq!(a.pos(), 1.0);
+        assert_eq!(a.v(), 1.0);
+    }
+
+    #[test]
+    fn load_sorts_keys() {
+        let path = std::env::temp_dir().join(format!("automation-{}.json", std::process::id()));
+        let json = r#"{
+            "unit": "Seconds",
+            "repeat": "Clamp",
+            "keys": [
+                { "t": 2.0, "v": 1.0 },
+                { "t": 0.0, "v": 0.0 },
+                { "t": 2.0, "v": 4.0 }
+            ]
+        }"#;
+        std::fs::write(&path, json).unwrap();
+
+        let a = Automation::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let times: Vec<f32> = a.keys().iter().map(|k| k.t).collect();
+        assert_eq!(times, vec![0.0, 2.0]);
+        assert_eq!(a.sample(1.0), 2.0);
+        assert_eq!(a.sample(3.0), 4.0);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::curve::Ease;
+use crate::time::Decay;
+
+// Something triggered that then evolves on its own, e.g. a flash on every beat
+pub trait Envelope {
+    fn trigger(&mut self);
+    fn update(&mut self, delta: f32);
+    fn v(&self) -> f32;
+    fn off(&self) -> bool;
+    // Set the envelope's main length in ms
+    fn t(&mut self, t: f32);
+}
+
+impl Envelope for Decay {
+    fn trigger(&mut self) {
+        self.set();
+    }
+
+    fn update(&mut self, delta: f32) {
+        Decay::update(self, delta);
+    }
+
+    fn v(&self) -> f32 {
+        Decay::v(self)
+    }
+
+    fn off(&self) -> bool {
+        Decay::off(self)
+    }
+
+    fn t(&mut self, t: f32) {
+        self.t = t;
+    }
+}
+
+// What a trigger does while the envelope is still running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Retrigger {
+    // Start over from 0
+    Reset,
+    // Attack again from the current level, without a click
+    Legato,
+    // Let the current run finish first
+    Ignore,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+// Attack, decay and release times in ms, sustain level in [0.0, 1.0]
+#[derive(Debug, Clone)]
+pub struct Adsr {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain: f32,
+    pub release: f32,
+    pub attack_shape: Ease,
+    pub decay_shape: Ease,
+    pub release_shape: Ease,
+    pub retrigger: Retrigger,
+    stage: Stage,
+    // Time into the current stage in ms
+    t: f32,
+    v: f32,
+    // Level at the start of the current stage
+    from: f32,
+    gate: bool,
+}
+
+impl Adsr {
+    pub fn new(attack: f32, decay: f32, sustain: f32, release: f32) -> Self {
+        Self {
+            attack,
+            decay,
+            sustain,
+            release,
+            attack_shape: Ease::Linear,
+            decay_shape: Ease::OutExp(4.0),
+            release_shape: Ease::OutExp(4.0),
+            retrigger: Retrigger::Reset,
+            stage: Stage::Idle,
+            t: 0.0,
+            v: 0.0,
+            from: 0.0,
+            gate: false,
+        }
+    }
+
+    // A punchy hit with a tail, for beat flashes: rises in `attack` then falls over `decay`
+    pub fn hit(attack: f32, decay: f32) -> Self {
+        Self::new(attack, decay, 0.0, 0.0)
+    }
+
+    pub fn shapes(mut self, attack: Ease, decay: Ease, release: Ease) -> Self {
+        self.attack_shape = attack;
+        self.decay_shape = decay;
+        self.release_shape = release;
+        self
+    }
+
+    pub fn retrigger(mut self, retrigger: Retrigger) -> Self {
+        self.retrigger = retrigger;
+        self
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    // Hold the envelope at sustain while the gate is on, release when it goes off
+    pub fn gate(&mut self, on: bool) {
+        if on && !self.gate {
+            self.start();
+        } else if !on && self.gate && self.stage != Stage::Idle {
+            self.enter(Stage::Release);
+        }
+
+        self.gate = on;
+    }
+
+    fn start(&mut self) {
+        match self.retrigger {
+            Retrigger::Ignore if self.stage != Stage::Idle => return,
+            Retrigger::Reset => self.v = 0.0,
+            _ => {}
+        }
+
+        self.enter(Stage::Attack);
+    }
+
+    fn enter(&mut self, stage: Stage) {
+        self.stage = stage;
+        self.from = self.v;
+        self.t = 0.0;
+    }
+
+    fn segment(&self, len: f32, to: f32, shape: Ease) -> f32 {
+        let f = if len <= 0.0 { 1.0 } else { self.t / len };
+        self.from + (to - self.from) * shape.apply(f)
+    }
+}
+
+impl Envelope for Adsr {
+    // Run through attack and decay, then release straight away
+    fn trigger(&mut self) {
+        self.gate = false;
+        self.start();
+    }
+
+    fn update(&mut self, delta: f32) {
+        self.t += delta * 1000.0;
+
+        // Zero length stages fall through within the same update
+        loop {
+            match self.stage {
+                Stage::Idle => return,
+                Stage::Attack => {
+                    self.v = self.segment(self.attack, 1.0, self.attack_shape);
+                    if self.t < self.attack {
+                        return;
+                    }
+                    self.t -= self.attack;
+                    self.stage = Stage::Decay;
+                    self.from = 1.0;
+                }
+                Stage::Decay => {
+                    self.v = self.segment(self.decay, self.sustain, self.decay_shape);
+                    if self.t < self.decay {
+                        return;
+                    }
+                    self.t -= self.decay;
+                    self.from = self.sustain;
+                    self.stage = if self.gate { Stage::Sustain } else { Stage::Release };
+                }
+                Stage::Sustain => {
+                    self.v = self.sustain;
+                    return;
+                }
+                Stage::Release => {
+                    self.v = self.segment(self.release, 0.0, self.release_shape);
+                    if self.t < self.release {
+                        return;
+                    }
+                    self.v = 0.0;
+                    self.stage = Stage::Idle;
+                }
+            }
+        }
+    }
+
+    fn v(&self) -> f32 {
+        self.v
+    }
+
+    fn off(&self) -> bool {
+        self.stage == Stage::Idle
+    }
+
+    // The tail after the attack
+    fn t(&mut self, t: f32) {
+        self.decay = t;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linear(attack: f32, decay: f32, sustain: f32, release: f32) -> Adsr {
+        Adsr::new(attack, decay, sustain, release).shapes(Ease::Linear, Ease::Linear, Ease::Linear)
+    }
+
+    fn near(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn stages() {
+        let mut env = linear(100.0, 100.0, 0.5, 200.0);
+        assert!(env.off());
+
+        env.gate(true);
+        env.update(0.05);
+        assert_eq!(env.stage(), Stage::Attack);
+        assert!(near(env.v(), 0.5));
+
+        env.update(0.1);
+        assert_eq!(env.stage(), Stage::Decay);
+        assert!(near(env.v(), 0.75));
+
+        // Holds at sustain for as long as the gate is on
+        env.update(0.1);
+        assert_eq!(env.stage(), Stage::Sustain);
+        env.update(10.0);
+        assert!(near(env.v(), 0.5));
+
+        env.gate(false);
+        env.update(0.1);
+        assert_eq!(env.stage(), Stage::Release);
+        assert!(near(env.v(), 0.25));
+
+        env.update(0.1);
+        assert!(env.off());
+        assert_eq!(env.v(), 0.0);
+    }
+
+    #[test]
+    fn release_mid_attack() {
+        let mut env = linear(100.0, 100.0, 0.5, 200.0);
+
+        env.gate(true);
+        env.update(0.05);
+        env.gate(false);
+
+        // Falls from where the attack got to, without reaching the peak
+        env.update(0.1);
+        assert_eq!(env.stage(), Stage::Release);
+        assert!(near(env.v(), 0.25));
+    }
+
+    #[test]
+    fn hit_runs_through() {
+        let mut env = linear(10.0, 100.0, 0.0, 0.0);
+
+        env.trigger();
+        env.update(0.06);
+        assert_eq!(env.stage(), Stage::Decay);
+        assert!(near(env.v(), 0.5));
+
+        // No sustain or release, it's over as soon as the decay is
+        env.update(0.05);
+        assert!(env.off());
+        assert_eq!(env.v(), 0.0);
+    }
+
+    #[test]
+    fn retrigger_modes() {
+        let half_way = |retrigger| {
+            let mut env = linear(100.0, 100.0, 0.0, 0.0).retrigger(retrigger);
+            env.trigger();
+            env.update(0.15);
+            assert!(near(env.v(), 0.5));
+            env
+        };
+
+        let mut env = half_way(Retrigger::Reset);
+        env.trigger();
+        env.update(0.0);
+        assert_eq!(env.stage(), Stage::Attack);
+        assert_eq!(env.v(), 0.0);
+
+        let mut env = half_way(Retrigger::Legato);
+        env.trigger();
+        env.update(0.05);
+        assert_eq!(env.stage(), Stage::Attack);
+        assert!(near(env.v(), 0.75));
+
+        let mut env = half_way(Retrigger::Ignore);
+        env.trigger();
+        env.update(0.025);
+        assert_eq!(env.stage(), Stage::Decay);
+        assert!(near(env.v(), 0.25));
+        env.update(0.05);
+        assert!(env.off());
+
+        // Only ignored while running
+        env.trigger();
+        assert_eq!(env.stage(), Stage::Attack);
+    }
+}
@@ -1,6 +1,12 @@
-use cgmath::{MetricSpace, Vector3};
-// use std::f32::consts::TAU;
-use splines::{Interpolation, Key};
+use cgmath::{MetricSpace, Rotation, Rotation3};
+use splines::{Interpolate, Interpolation, Key};
+
+use crate::curve::Repeat;
+use crate::gfx::camera::Camera;
+use crate::gfx::frame::Frame;
+use crate::gfx::scene::{Node, Transform};
+use crate::math::prelude::*;
+use crate::resource;
 
 pub use splines::Spline;
 
@@ -23,140 +29,303 @@ pub fn partial_distance<T: MetricSpace<Metric = f32> + Copy>(points: &[T]) -> Ve
     dists
 }
 
-pub fn linear<T: MetricSpace<Metric = f32> + Copy>(points: &[T], t: f32) -> Spline<f32, T> {
-    let n = points.len();
-    assert!(n >= 3);
+// Sample anywhere, holding the first and last values outside the keys.
+// Only None for an empty spline
+pub fn sample<V: Interpolate<f32>>(spline: &Spline<f32, V>, t: f32) -> Option<V> {
+    let keys = spline.keys();
+    let (first, last) = (keys.first()?, keys.last()?);
 
-    // Compute normalized distances from [0, t]
-    let mut dists = partial_distance(points);
-    let total = dists[n - 1];
-    dists.iter_mut().for_each(|d| {
-        *d = (*d / total) * t;
-    });
+    if t <= first.t {
+        return Some(first.value);
+    }
+    if t >= last.t {
+        return Some(last.value);
+    }
 
-    let mut keys = Vec::new();
+    spline.sample(t).or_else(|| spline.clamped_sample(t))
+}
 
-    keys.extend(
-        points[0..n - 1]
-            .iter()
-            .zip(dists.iter())
-            .map(|(p, t)| Key::new(*t, *p, Interpolation::Linear)),
-    );
-    keys.push(Key::new(t + 0.1, points[n - 1], Interpolation::Linear));
+// Repeated points would give keys at the same time
+fn dedup<T: MetricSpace<Metric = f32> + Copy>(points: &[T]) -> Vec<T> {
+    let mut out: Vec<T> = Vec::with_capacity(points.len());
+    for p in points {
+        if out.last().map_or(true, |o| o.distance(*p) > 0.0) {
+            out.push(*p);
+        }
+    }
+    out
+}
+
+// Key times spread over [0, t] by the distance travelled
+fn times<T: MetricSpace<Metric = f32> + Copy>(points: &[T], t: f32) -> Vec<f32> {
+    let dists = partial_distance(points);
+    let total = dists.last().copied().unwrap_or(0.0);
+    dists
+        .iter()
+        .map(|d| if total > 0.0 { d / total * t } else { 0.0 })
+        .collect()
+}
+
+pub fn linear<T: MetricSpace<Metric = f32> + Copy>(points: &[T], t: f32) -> Spline<f32, T> {
+    let points = dedup(points);
+    let keys = points
+        .iter()
+        .zip(times(&points, t))
+        .map(|(p, t)| Key::new(t, *p, Interpolation::Linear))
+        .collect();
 
     Spline::from_vec(keys)
 }
 
 pub fn catmull<T: MetricSpace<Metric = f32> + Copy>(points: &[T], t: f32) -> Spline<f32, T> {
+    let points = dedup(points);
     let n = points.len();
-    assert!(n >= 3);
-
-    // Compute normalized distances from [0, t]
-    let mut dists = partial_distance(points);
-    let total = dists[n - 1];
-    dists.iter_mut().for_each(|d| {
-        *d = (*d / total) * t;
-    });
+    if n < 2 {
+        return linear(&points, t);
+    }
 
-    let mut keys = Vec::new();
+    let times = times(&points, t);
 
-    keys.push(Key::new(0.0, points[0], Interpolation::default()));
+    // Catmull-Rom needs a key either side of each segment, repeat the end
+    // points as far out as their neighbours
+    let mut keys = Vec::with_capacity(n + 2);
+    keys.push(Key::new(-times[1], points[0], Interpolation::default()));
     keys.extend(
-        points[0..n - 1]
+        points
             .iter()
-            .zip(dists.iter())
+            .zip(times.iter())
             .map(|(p, t)| Key::new(*t, *p, Interpolation::CatmullRom)),
     );
-    keys.push(Key::new(t, points[n - 1], Interpolation::default()));
-    keys.push(Key::new(t, points[n - 1], Interpolation::default()));
+    keys.push(Key::new(
+        2.0 * t - times[n - 2],
+        points[n - 1],
+        Interpolation::default(),
+    ));
 
     Spline::from_vec(keys)
 }
 
+// Closed through the last point back to the first, sample over [0, t)
 pub fn catmull_loop<T: MetricSpace<Metric = f32> + Copy>(points: &[T], t: f32) -> Spline<f32, T> {
+    let mut points = dedup(points);
+    if points.len() > 1 && points[0].distance(points[points.len() - 1]) == 0.0 {
+        points.pop();
+    }
     let n = points.len();
-    assert!(n >= 3);
-
-    // Compute normalized distances from [0, t] plus the connecting distance
-    let mut dists = partial_distance(points);
-    let join = points[n - 1].distance(points[0]);
-    let total = dists[n - 1] + join;
-    dists.push(total);
-    dists.iter_mut().for_each(|d| {
-        *d = (*d / total) * t;
-    });
+    if n < 2 {
+        return linear(&points, t);
+    }
 
-    let mut keys = Vec::new();
+    // Normalized distances with the closing segment
+    let mut closed = points.clone();
+    closed.push(points[0]);
+    let times = times(&closed, t);
 
-    keys.push(Key::new(0.0, points[n - 1], Interpolation::default()));
+    // Neighbours for the first and closing segments come from around the loop
+    let mut keys = Vec::with_capacity(n + 3);
+    keys.push(Key::new(times[n - 1] - t, points[n - 1], Interpolation::default()));
     keys.extend(
-        points[0..n]
+        closed
             .iter()
-            .zip(dists.iter())
+            .zip(times.iter())
             .map(|(p, t)| Key::new(*t, *p, Interpolation::CatmullRom)),
     );
-    keys.push(Key::new(t, points[0], Interpolation::CatmullRom));
-    keys.push(Key::new(t + dists[1], points[1], Interpolation::default()));
-    keys.push(Key::new(t + dists[2], points[2], Interpolation::default()));
+    keys.push(Key::new(t + times[1], points[1], Interpolation::default()));
+
+    Spline::from_vec(keys)
+}
+
+// How a camera path moves between keyframes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathInterp {
+    Linear,
+    CatmullRom,
+}
+
+// Keys evenly spaced over [0, t], as baked keyframes are one per frame
+fn even<V: Interpolate<f32>>(values: &[V], t: f32, interp: PathInterp) -> Spline<f32, V> {
+    let n = values.len();
+    if n < 2 {
+        return Spline::from_vec(values.iter().map(|v| Key::new(0.0, *v, Interpolation::Linear)).collect());
+    }
+
+    let dt = t / (n - 1) as f32;
+    let interpolation = match interp {
+        PathInterp::Linear => Interpolation::Linear,
+        PathInterp::CatmullRom => Interpolation::CatmullRom,
+    };
+
+    let mut keys = Vec::with_capacity(n + 2);
+    keys.push(Key::new(-dt, values[0], Interpolation::default()));
+    keys.extend(values.iter().enumerate().map(|(i, v)| Key::new(i as f32 * dt, *v, interpolation)));
+    keys.push(Key::new(t + dt, values[n - 1], Interpolation::default()));
 
     Spline::from_vec(keys)
 }
 
+// A camera fly-through baked from Blender into a .ckf file: six comma separated
+// rows of per-frame rotation x, y, z (XYZ Euler, radians) and position x, y, z
 pub struct CameraPath3D {
-    position: Spline<f32, Vector3<f32>>,
-    rotation: Spline<f32, Vector3<f32>>,
+    frames: Vec<(Vector3, Vector3)>,
+    position: Spline<f32, Vector3>,
+    rotation: Spline<f32, Vector3>,
+    // Length in seconds at normal speed
     t: f32,
+    pub repeat: Repeat,
+    // Playback speed, 2.0 is twice as fast
+    pub speed: f32,
 }
 
-// impl CameraPath3D {
-//     pub fn new(file: &str, t: f32) -> Self {
-//         //let data = lib::read_resource(file);
-
-//         let mut keyframes = data
-//             .split("\n")
-//             .map(|line| line.split(",").map(|f| f.parse::<f32>().unwrap()));
-
-//         let rx = keyframes.next().unwrap();
-//         let ry = keyframes.next().unwrap();
-//         let rz = keyframes.next().unwrap();
-//         let angles = rx
-//             .zip(ry)
-//             .zip(rz)
-//             .map(|((x, y), z)| Vector3::new(x, y, z + TAU / 4.0))
-//             .collect::<Vec<_>>();
-
-//         let px = keyframes.next().unwrap();
-//         let py = keyframes.next().unwrap();
-//         let pz = keyframes.next().unwrap();
-//         let points = px
-//             .zip(py)
-//             .zip(pz)
-//             .map(|((x, y), z)| Vector3::new(x, z, y))
-//             .collect::<Vec<_>>();
-
-//         let position = linear(&points, t);
-//         let rotation = linear(&angles, t);
-
-//         Self {
-//             position,
-//             rotation,
-//             t,
-//         }
-//     }
-
-//     fn update_internal(&self, camera: &mut Camera, t: f32) {
-//         let pos = self.position.sample(t).unwrap();
-//         let rot = self.rotation.sample(t).unwrap();
-
-//         camera.desc.pos = pos;
-//         camera.desc.rotation = CameraRotation::EulerAngles(rot);
-//     }
-
-//     pub fn update(&self, camera: &mut Camera, t: f32) {
-//         self.update_internal(camera, t % self.t);
-//     }
-
-//     pub fn update_clamp(&self, camera: &mut Camera, t: f32) {
-//         self.update_internal(camera, t.min(self.t));
-//     }
-// }
+impl CameraPath3D {
+    pub fn new(file: &str, t: f32) -> Self {
+        Self::parse(&resource::read_str(file), t).unwrap_or_else(|| panic!("Invalid camera path {}", file))
+    }
+
+    pub fn parse(data: &str, t: f32) -> Option<Self> {
+        let rows = data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| line.split(',').map(|f| f.trim().parse::<f32>()).collect::<Result<Vec<_>, _>>())
+            .collect::<Result<Vec<_>, _>>();
+
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(e) => {
+                log::warn!("Invalid camera keyframe: {}", e);
+                return None;
+            }
+        };
+
+        let n = rows.iter().map(Vec::len).min().unwrap_or(0);
+        if rows.len() != 6 || n == 0 {
+            log::warn!("Camera paths need 6 rows of keyframes");
+            return None;
+        }
+
+        // Some exports have a few more rotation than position keys or the other way
+        // round, the extra ones at the end are dropped
+        if rows.iter().any(|row| row.len() != n) {
+            let lens = rows.iter().map(Vec::len).collect::<Vec<_>>();
+            log::warn!("Camera keyframe rows differ in length {:?}, using the first {}", lens, n);
+        }
+
+        let frames = (0..n)
+            .map(|i| {
+                let rotation = Vector3::new(rows[0][i], rows[1][i], rows[2][i]);
+                let position = Vector3::new(rows[3][i], rows[4][i], rows[5][i]);
+                (rotation, position)
+            })
+            .collect::<Vec<_>>();
+
+        let mut path = Self {
+            frames,
+            position: Spline::from_vec(Vec::new()),
+            rotation: Spline::from_vec(Vec::new()),
+            t,
+            repeat: Repeat::Clamp,
+            speed: 1.0,
+        };
+        path.interp(PathInterp::Linear, PathInterp::Linear);
+
+        Some(path)
+    }
+
+    // Catmull-Rom smooths out sparse keyframes, but can overshoot
+    pub fn interp(&mut self, position: PathInterp, rotation: PathInterp) {
+        let rotations = self.frames.iter().map(|(r, _)| *r).collect::<Vec<_>>();
+        let positions = self.frames.iter().map(|(_, p)| *p).collect::<Vec<_>>();
+
+        self.position = even(&positions, self.t, position);
+        self.rotation = even(&rotations, self.t, rotation);
+    }
+
+    pub fn with_interp(mut self, position: PathInterp, rotation: PathInterp) -> Self {
+        self.interp(position, rotation);
+        self
+    }
+
+    pub fn repeat(mut self, repeat: Repeat) -> Self {
+        self.repeat = repeat;
+        self
+    }
+
+    pub fn speed(mut self, speed: f32) -> Self {
+        self.speed = speed;
+        self
+    }
+
+    // Length in seconds at the current speed
+    pub fn duration(&self) -> f32 {
+        if self.speed > 0.0 {
+            self.t / self.speed
+        } else {
+            f32::INFINITY
+        }
+    }
+
+    // Camera placement at `t` seconds, in Y up space
+    pub fn transform(&self, t: f32) -> Transform {
+        let t = self.repeat.apply(t * self.speed, 0.0, self.t);
+        let p = sample(&self.position, t).unwrap_or_else(Vector3::zero);
+        let r = sample(&self.rotation, t).unwrap_or_else(Vector3::zero);
+
+        // Blender's XYZ Euler applies X first. Blender is Z up, turn it a quarter
+        // around X to Y up as its exporters do
+        let up = Quat::from_angle_x(Deg(-90.0));
+        let rotate = Quat::from_angle_z(Rad(r.z)) * Quat::from_angle_y(Rad(r.y)) * Quat::from_angle_x(Rad(r.x));
+
+        Transform {
+            translate: up.rotate_vector(p),
+            rotate: up * rotate,
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    pub fn view(&self, t: f32) -> Matrix4 {
+        Matrix4::from(self.transform(t)).invert().unwrap_or_else(Matrix4::identity)
+    }
+
+    // Upload the view at `t` seconds straight to a camera
+    pub fn update(&self, frame: &mut Frame, camera: &Camera, t: f32) {
+        camera.view.upload(frame, &self.view(t));
+    }
+
+    // Move a scene's camera node instead, Scene::update picks it up
+    pub fn update_node(&self, node: &mut Node, t: f32) {
+        let transform = self.transform(t);
+        node.transform.translate = transform.translate;
+        node.transform.rotate = transform.rotate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_keyframes() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../resources/keyframes");
+
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let file = entry.unwrap().path();
+            if file.extension().map_or(true, |e| e != "ckf") {
+                continue;
+            }
+
+            let data = std::fs::read_to_string(&file).unwrap();
+            let path = CameraPath3D::parse(&data, 30.0);
+            assert!(path.is_some(), "Failed to parse {}", file.display());
+        }
+    }
+
+    #[test]
+    fn truncates_uneven_rows() {
+        let data = "0,0,0\n0,0,0\n0,0,0\n1,2\n3,4\n5,6\n";
+        let path = CameraPath3D::parse(data, 1.0).unwrap();
+        assert_eq!(path.frames.len(), 2);
+        assert_eq!(path.frames[1].1, Vector3::new(2.0, 4.0, 6.0));
+
+        assert!(CameraPath3D::parse("1,2\n3,4\n", 1.0).is_none());
+        assert!(CameraPath3D::parse("1\n2\n3\n4\n5\n", 1.0).is_none());
+    }
+}
@@ -0,0 +1,298 @@
+use std::f32::consts::PI;
+
+use crate::time::BeatClock;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wave {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    // A new random level every cycle
+    SampleHold,
+    // Random levels eased into each other
+    SmoothRandom,
+}
+
+// How long one cycle lasts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rate {
+    Hz(f32),
+    // Cycle length in beats of a BeatClock
+    Beats(f32),
+}
+
+impl Rate {
+    // A note value, e.g. division(1, 8) is an eighth note, half a beat
+    pub fn division(num: u32, den: u32) -> Self {
+        Rate::Beats(4.0 * num as f32 / den as f32)
+    }
+
+    pub fn bars(n: f32) -> Self {
+        Rate::Beats(4.0 * n)
+    }
+
+    pub fn triplet(self) -> Self {
+        self.scale(2.0 / 3.0)
+    }
+
+    pub fn dotted(self) -> Self {
+        self.scale(1.5)
+    }
+
+    fn scale(self, k: f32) -> Self {
+        match self {
+            Rate::Hz(hz) => Rate::Hz(hz / k),
+            Rate::Beats(b) => Rate::Beats(b * k),
+        }
+    }
+
+    // Parse "2hz", "1/4", "1/8T", "1/16.", "2 bars" or a plain number of beats
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().to_lowercase();
+
+        if let Some(hz) = s.strip_suffix("hz") {
+            return hz.trim().parse().ok().map(Rate::Hz);
+        }
+
+        if let Some(n) = s.strip_suffix("bars").or_else(|| s.strip_suffix("bar")) {
+            return n.trim().parse().ok().map(Rate::bars);
+        }
+
+        let (s, k) = if let Some(s) = s.strip_suffix('t') {
+            (s, 2.0 / 3.0)
+        } else if let Some(s) = s.strip_suffix('.') {
+            (s, 1.5)
+        } else {
+            (s.as_str(), 1.0)
+        };
+
+        let rate = match s.find('/') {
+            Some(i) => Rate::division(s[..i].trim().parse().ok()?, s[i + 1..].trim().parse().ok()?),
+            None => Rate::Beats(s.parse().ok()?),
+        };
+
+        Some(rate.scale(k))
+    }
+}
+
+pub struct Lfo {
+    pub wave: Wave,
+    pub rate: Rate,
+    // Phase offset in cycles
+    pub offset: f32,
+    // Restart the cycle on every beat passed to update()
+    pub retrigger: bool,
+    // Cycles elapsed
+    pos: f64,
+    // Clock periods counted since the last restart, for beat rates
+    beats: u64,
+    rng: u32,
+    prev: f32,
+    next: f32,
+}
+
+impl Lfo {
+    pub fn new(wave: Wave, rate: Rate) -> Self {
+        let mut lfo = Self {
+            wave,
+            rate,
+            offset: 0.0,
+            retrigger: false,
+            pos: 0.0,
+            beats: 0,
+            rng: 0x9E37_79B9,
+            prev: 0.0,
+            next: 0.0,
+        };
+        lfo.prev = lfo.random();
+        lfo.next = lfo.random();
+        lfo
+    }
+
+    pub fn offset(mut self, offset: f32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn retrigger(mut self, retrigger: bool) -> Self {
+        self.retrigger = retrigger;
+        self
+    }
+
+    // Different seeds give independent random waves
+    pub fn seed(mut self, seed: u32) -> Self {
+        self.rng = seed.max(1);
+        self.prev = self.random();
+        self.next = self.random();
+        self
+    }
+
+    pub fn restart(&mut self) {
+        self.pos = 0.0;
+        self.beats = 0;
+    }
+
+    // `beat` is what clock.update() returned this frame.
+    // Beat rates follow the clock's phase so they never drift off the beat. A clock
+    // period is `mul` beats, so they stay note values whatever the multiplier
+    pub fn update(&mut self, delta: f32, clock: &BeatClock, beat: bool) {
+        if beat {
+            if self.retrigger {
+                self.restart();
+            } else {
+                self.beats += 1;
+            }
+        }
+
+        let pos = match self.rate {
+            Rate::Hz(hz) => self.pos + (delta * hz) as f64,
+            Rate::Beats(len) if len > 0.0 => {
+                (self.beats as f64 + clock.phase() as f64) * clock.mul as f64 / len as f64
+            }
+            Rate::Beats(_) => self.pos,
+        };
+
+        // A new random level for every cycle crossed
+        if pos.floor() > self.pos.floor() || (beat && self.retrigger) {
+            self.prev = self.next;
+            self.next = self.random();
+        }
+
+        self.pos = pos;
+    }
+
+    // Position in the current cycle in [0.0, 1.0)
+    pub fn phase(&self) -> f32 {
+        (self.pos as f32 + self.offset).rem_euclid(1.0)
+    }
+
+    // Bipolar output in [-1.0, 1.0]
+    pub fn v(&self) -> f32 {
+        let p = self.phase();
+
+        match self.wave {
+            Wave::Sine => (2.0 * PI * p).sin(),
+            Wave::Triangle => 4.0 * ((p + 0.75).rem_euclid(1.0) - 0.5).abs() - 1.0,
+            Wave::Saw => 2.0 * p - 1.0,
+            Wave::Square => {
+                if p < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Wave::SampleHold => self.next,
+            Wave::SmoothRandom => {
+                // Eased across the cycle the levels were drawn for, ignoring the offset
+                let p = self.pos.fract() as f32;
+                let f = p * p * (3.0 - 2.0 * p);
+                self.prev + (self.next - self.prev) * f
+            }
+        }
+    }
+
+    // Unipolar output in [0.0, 1.0]
+    pub fn unipolar(&self) -> f32 {
+        0.5 + 0.5 * self.v()
+    }
+
+    // xorshift32, mapped onto [-1.0, 1.0]
+    fn random(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(wave: Wave, phase: f32) -> f32 {
+        Lfo::new(wave, Rate::Hz(0.0)).offset(phase).v()
+    }
+
+    fn beats(rate: Option<Rate>) -> f32 {
+        match rate {
+            Some(Rate::Beats(b)) => b,
+            _ => panic!("Not a beat rate: {:?}", rate),
+        }
+    }
+
+    #[test]
+    fn waves() {
+        assert!((at(Wave::Sine, 0.25) - 1.0).abs() < 1e-6);
+        assert!(at(Wave::Sine, 0.0).abs() < 1e-6);
+
+        assert_eq!(at(Wave::Triangle, 0.0), 0.0);
+        assert_eq!(at(Wave::Triangle, 0.25), 1.0);
+        assert_eq!(at(Wave::Triangle, 0.5), 0.0);
+        assert_eq!(at(Wave::Triangle, 0.75), -1.0);
+
+        assert_eq!(at(Wave::Saw, 0.0), -1.0);
+        assert_eq!(at(Wave::Saw, 0.75), 0.5);
+
+        assert_eq!(at(Wave::Square, 0.25), 1.0);
+        assert_eq!(at(Wave::Square, 0.75), -1.0);
+
+        let lfo = Lfo::new(Wave::Sine, Rate::Hz(0.0)).offset(0.25);
+        assert!((lfo.unipolar() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn sample_hold_changes_each_cycle() {
+        let clock = BeatClock::new(120.0);
+        let mut lfo = Lfo::new(Wave::SampleHold, Rate::Hz(1.0));
+
+        lfo.update(0.25, &clock, false);
+        let v = lfo.v();
+        lfo.update(0.25, &clock, false);
+        assert_eq!(lfo.v(), v);
+
+        lfo.update(0.75, &clock, false);
+        assert_ne!(lfo.v(), v);
+        assert!(lfo.v().abs() <= 1.0);
+    }
+
+    #[test]
+    fn parse_rates() {
+        assert_eq!(Rate::parse("2hz"), Some(Rate::Hz(2.0)));
+        assert_eq!(Rate::parse("1/4"), Some(Rate::Beats(1.0)));
+        assert_eq!(Rate::parse("3"), Some(Rate::Beats(3.0)));
+        assert_eq!(Rate::parse("2 bars"), Some(Rate::Beats(8.0)));
+        assert_eq!(Rate::parse("1 Bar"), Some(Rate::Beats(4.0)));
+        assert_eq!(Rate::parse("1/16."), Some(Rate::Beats(0.375)));
+        assert!((beats(Rate::parse("1/8T")) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(Rate::parse("fast"), None);
+        assert_eq!(Rate::parse("1/x"), None);
+    }
+
+    #[test]
+    fn beat_rates_ignore_clock_multiplier() {
+        // Half a beat at 120 BPM, a quarter of the way through a period of two beats
+        let mut clock = BeatClock::new(120.0);
+        clock.mul = 2.0;
+        let beat = clock.update(0.25);
+
+        let mut lfo = Lfo::new(Wave::Saw, Rate::division(1, 4));
+        lfo.update(0.25, &clock, beat);
+        assert!((lfo.phase() - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn retrigger() {
+        for &(retrigger, phase) in &[(false, 0.25), (true, 0.0)] {
+            let mut clock = BeatClock::new(120.0);
+            let mut lfo = Lfo::new(Wave::Saw, Rate::Beats(4.0)).retrigger(retrigger);
+
+            for _ in 0..2 {
+                let beat = clock.update(0.25);
+                lfo.update(0.25, &clock, beat);
+            }
+
+            assert!((lfo.phase() - phase).abs() < 1e-4, "{} {}", retrigger, lfo.phase());
+        }
+    }
+}
@@ -8,10 +8,18 @@ pub mod resource;
 pub mod window;
 pub mod gfx;
 pub mod audio;
-// pub mod interp;
+pub mod interp;
 pub mod midi;
-// pub mod osc;
+pub mod osc;
 pub mod time;
+pub mod envelope;
+pub mod lfo;
+pub mod modulation;
+pub mod link;
+pub mod param;
+pub mod preset;
+pub mod timeline;
+pub mod curve;
 // pub mod twitch;
 // pub mod wavefront;
 pub mod procedural;
@@ -0,0 +1,696 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::time::BeatClock;
+
+// An Ableton Link compatible peer. Peers find each other over UDP multicast, agree on
+// a session, measure the session's shared "ghost" clock against their own, and share
+// a timeline mapping ghost time to beats so tempo and phase line up everywhere.
+
+const MULTICAST: Ipv4Addr = Ipv4Addr::new(224, 76, 78, 75);
+const PORT: u16 = 20808;
+
+const DISCOVERY: &[u8] = b"_asdp_v\x01";
+const MEASUREMENT: &[u8] = b"_link_v\x01";
+
+const ALIVE: u8 = 1;
+const RESPONSE: u8 = 2;
+const BYEBYE: u8 = 3;
+
+const PING: u8 = 1;
+const PONG: u8 = 2;
+
+const TIMELINE: &[u8; 4] = b"tmln";
+const SESSION: &[u8; 4] = b"sess";
+const START_STOP: &[u8; 4] = b"stst";
+const ENDPOINT: &[u8; 4] = b"mep4";
+const HOST_TIME: &[u8; 4] = b"__ht";
+const GHOST_TIME: &[u8; 4] = b"__gt";
+
+// Seconds peers remember us without hearing from us
+const TTL: u8 = 5;
+const BROADCAST: Duration = Duration::from_millis(250);
+// Round trips measured before joining another session, the median is used
+const SAMPLES: usize = 5;
+const MEASURE_TIMEOUT: Duration = Duration::from_secs(1);
+// Sessions whose ghost clocks are closer than this, in microseconds, are the same age
+const SESSION_EPS: i64 = 500_000;
+// BeatClock only needs the phase, wrap beats so they stay precise as f32
+const QUANTUM: f64 = 64.0;
+
+type NodeId = [u8; 8];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Timeline {
+    // Microseconds per beat
+    tempo: i64,
+    // Micro-beats at time_origin
+    beat_origin: i64,
+    // Ghost time in microseconds
+    time_origin: i64,
+}
+
+// Microseconds per beat, None for tempos that don't make one
+fn tempo(bpm: f32) -> Option<i64> {
+    if bpm > 0.0 {
+        Some((60_000_000.0 / bpm) as i64).filter(|&t| t > 0)
+    } else {
+        None
+    }
+}
+
+impl Timeline {
+    fn new(bpm: f32, ghost: i64) -> Option<Self> {
+        Some(Self {
+            tempo: tempo(bpm)?,
+            beat_origin: 0,
+            time_origin: ghost,
+        })
+    }
+
+    fn bpm(&self) -> f32 {
+        60_000_000.0 / self.tempo as f32
+    }
+
+    // Micro-beats at a ghost time
+    fn beats(&self, ghost: i64) -> i64 {
+        self.beat_origin + ((ghost - self.time_origin) as i128 * 1_000_000 / self.tempo as i128) as i64
+    }
+
+    // Keep the beat at `ghost`, with a new tempo from there on
+    fn retempo(&self, bpm: f32, ghost: i64) -> Option<Self> {
+        Some(Self {
+            tempo: tempo(bpm)?,
+            beat_origin: self.beats(ghost),
+            time_origin: ghost,
+        })
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(24);
+        data.extend_from_slice(&self.tempo.to_be_bytes());
+        data.extend_from_slice(&self.beat_origin.to_be_bytes());
+        data.extend_from_slice(&self.time_origin.to_be_bytes());
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 24 {
+            return None;
+        }
+
+        let timeline = Self {
+            tempo: i64_at(data, 0),
+            beat_origin: i64_at(data, 8),
+            time_origin: i64_at(data, 16),
+        };
+
+        Some(timeline).filter(|t| t.tempo > 0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StartStop {
+    playing: bool,
+    // Micro-beats when the state changed
+    beats: i64,
+    // Ghost time of the change, the newest change wins
+    timestamp: i64,
+}
+
+impl StartStop {
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(17);
+        data.push(self.playing as u8);
+        data.extend_from_slice(&self.beats.to_be_bytes());
+        data.extend_from_slice(&self.timestamp.to_be_bytes());
+        data
+    }
+
+    fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < 17 {
+            return None;
+        }
+
+        Some(Self {
+            playing: data[0] != 0,
+            beats: i64_at(data, 1),
+            timestamp: i64_at(data, 9),
+        })
+    }
+}
+
+struct Peer {
+    session: NodeId,
+    timeline: Timeline,
+    expires: Instant,
+}
+
+// Measuring the ghost clock of a session we're about to join
+struct Measure {
+    session: NodeId,
+    endpoint: SocketAddr,
+    samples: Vec<i64>,
+    sent: Instant,
+}
+
+struct State {
+    node: NodeId,
+    session: NodeId,
+    timeline: Timeline,
+    start_stop: StartStop,
+    // Ghost time minus host time
+    offset: i64,
+    peers: HashMap<NodeId, Peer>,
+    measure: Option<Measure>,
+    // Sessions we measured and stayed out of, until their peers are gone
+    others: HashSet<NodeId>,
+    epoch: Instant,
+    // Where peers send measurement pings
+    endpoint: SocketAddrV4,
+}
+
+impl State {
+    fn host(&self) -> i64 {
+        self.epoch.elapsed().as_micros() as i64
+    }
+
+    fn ghost(&self) -> i64 {
+        self.host() + self.offset
+    }
+
+    fn discovery(&self, kind: u8) -> Vec<u8> {
+        let mut buf = DISCOVERY.to_vec();
+        buf.push(kind);
+        buf.push(TTL);
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&self.node);
+
+        if kind != BYEBYE {
+            let mut endpoint = self.endpoint.ip().octets().to_vec();
+            endpoint.extend_from_slice(&self.endpoint.port().to_be_bytes());
+
+            entry(&mut buf, TIMELINE, &self.timeline.encode());
+            entry(&mut buf, SESSION, &self.session);
+            entry(&mut buf, START_STOP, &self.start_stop.encode());
+            entry(&mut buf, ENDPOINT, &endpoint);
+        }
+
+        buf
+    }
+}
+
+pub struct Link {
+    state: Arc<Mutex<State>>,
+    socket: Arc<UdpSocket>,
+    running: Arc<AtomicBool>,
+    // Tempo last handed to the BeatClock, to tell local tempo changes apart
+    last: Option<f32>,
+}
+
+impl Link {
+    // Join or found a session, starting at `bpm` if there are no peers yet
+    pub fn init(bpm: f32) -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).expect("Failed to bind Link socket");
+        socket.set_read_timeout(Some(BROADCAST / 4)).unwrap();
+        let port = socket.local_addr().unwrap().port();
+
+        let multicast = multicast().expect("Failed to join Link multicast group");
+        multicast.set_read_timeout(Some(BROADCAST)).unwrap();
+
+        let node = node_id();
+        let epoch = Instant::now();
+        let state = Arc::new(Mutex::new(State {
+            node,
+            session: node,
+            timeline: Timeline::new(bpm, 0).expect("Invalid Link tempo"),
+            start_stop: StartStop {
+                playing: false,
+                beats: 0,
+                timestamp: 0,
+            },
+            offset: 0,
+            peers: HashMap::new(),
+            measure: None,
+            others: HashSet::new(),
+            epoch,
+            endpoint: SocketAddrV4::new(local_ip(), port),
+        }));
+
+        let socket = Arc::new(socket);
+        let running = Arc::new(AtomicBool::new(true));
+
+        {
+            let (state, socket, running) = (Arc::clone(&state), Arc::clone(&socket), Arc::clone(&running));
+            thread::spawn(move || listen(&multicast, &socket, &state, &running));
+        }
+
+        {
+            let (state, socket, running) = (Arc::clone(&state), Arc::clone(&socket), Arc::clone(&running));
+            thread::spawn(move || serve(&socket, &state, &running));
+        }
+
+        Self {
+            state,
+            socket,
+            running,
+            last: None,
+        }
+    }
+
+    // Other peers in our session
+    pub fn peers(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        state.peers.values().filter(|p| p.session == state.session).count()
+    }
+
+    pub fn bpm(&self) -> f32 {
+        self.state.lock().unwrap().timeline.bpm()
+    }
+
+    // Session beat position
+    pub fn beats(&self) -> f64 {
+        let state = self.state.lock().unwrap();
+        state.timeline.beats(state.ghost()) as f64 / 1_000_000.0
+    }
+
+    pub fn set_bpm(&mut self, bpm: f32) {
+        let mut state = self.state.lock().unwrap();
+        let ghost = state.ghost();
+        state.timeline = match state.timeline.retempo(bpm, ghost) {
+            Some(timeline) => timeline,
+            None => {
+                log::warn!("Link: Ignoring invalid tempo {}", bpm);
+                return;
+            }
+        };
+        self.last = Some(bpm);
+        self.broadcast(&state);
+    }
+
+    pub fn playing(&self) -> bool {
+        self.state.lock().unwrap().start_stop.playing
+    }
+
+    pub fn set_playing(&self, playing: bool) {
+        let mut state = self.state.lock().unwrap();
+        let ghost = state.ghost();
+        state.start_stop = StartStop {
+            playing,
+            beats: state.timeline.beats(ghost),
+            timestamp: ghost,
+        };
+        self.broadcast(&state);
+    }
+
+    // Lock the clock to the session, and push local tempo changes to it.
+    // Returns true on a beat, like BeatClock::update
+    pub fn update(&mut self, clock: &mut BeatClock) -> bool {
+        if let Some(last) = self.last {
+            if (clock.bpm - last).abs() > 1e-3 {
+                self.set_bpm(clock.bpm);
+            }
+        }
+
+        let (bpm, beats) = {
+            let state = self.state.lock().unwrap();
+            let beats = state.timeline.beats(state.ghost()) as f64 / 1_000_000.0;
+            (state.timeline.bpm(), beats)
+        };
+
+        self.last = Some(bpm);
+        clock.follow(bpm, beats.rem_euclid(QUANTUM) as f32)
+    }
+
+    fn broadcast(&self, state: &State) {
+        if let Err(e) = self.socket.send_to(&state.discovery(ALIVE), (MULTICAST, PORT)) {
+            log::warn!("Link: Broadcast failed: {}", e);
+        }
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        let state = self.state.lock().unwrap();
+        let _ = self.socket.send_to(&state.discovery(BYEBYE), (MULTICAST, PORT));
+    }
+}
+
+// Discovery messages from the multicast group
+fn listen(multicast: &UdpSocket, socket: &UdpSocket, state: &Mutex<State>, running: &AtomicBool) {
+    let mut buf = [0u8; 512];
+
+    while running.load(Ordering::SeqCst) {
+        if let Ok((n, from)) = multicast.recv_from(&mut buf) {
+            if buf[..n].starts_with(DISCOVERY) {
+                discovery(&mut state.lock().unwrap(), from, &buf[..n], socket);
+            }
+        }
+    }
+}
+
+// Unicast responses and measurement, plus our own periodic broadcasts
+fn serve(socket: &UdpSocket, state: &Mutex<State>, running: &AtomicBool) {
+    let mut buf = [0u8; 512];
+    let mut broadcast = Instant::now() - BROADCAST;
+
+    while running.load(Ordering::SeqCst) {
+        if let Ok((n, from)) = socket.recv_from(&mut buf) {
+            let mut state = state.lock().unwrap();
+
+            if buf[..n].starts_with(DISCOVERY) {
+                discovery(&mut state, from, &buf[..n], socket);
+            } else if buf[..n].starts_with(MEASUREMENT) {
+                measurement(&mut state, from, &buf[..n], socket);
+            }
+        }
+
+        let mut state = state.lock().unwrap();
+        let now = Instant::now();
+
+        state.peers.retain(|_, p| p.expires > now);
+
+        let sessions: HashSet<NodeId> = state.peers.values().map(|p| p.session).collect();
+        state.others.retain(|s| sessions.contains(s));
+
+        if state.measure.as_ref().map_or(false, |m| now - m.sent > MEASURE_TIMEOUT) {
+            log::warn!("Link: Measurement timed out");
+            state.measure = None;
+        }
+
+        if now - broadcast >= BROADCAST {
+            broadcast = now;
+            let _ = socket.send_to(&state.discovery(ALIVE), (MULTICAST, PORT));
+        }
+    }
+}
+
+fn discovery(state: &mut State, from: SocketAddr, data: &[u8], socket: &UdpSocket) {
+    if data.len() < 20 {
+        return;
+    }
+
+    let kind = data[8];
+    let ttl = data[9];
+    let node: NodeId = data[12..20].try_into().unwrap();
+
+    if node == state.node {
+        return;
+    }
+
+    if kind == BYEBYE {
+        state.peers.remove(&node);
+        return;
+    }
+
+    let e = entries(&data[20..]);
+    let timeline = match e.get(TIMELINE).and_then(|d| Timeline::decode(d)) {
+        Some(timeline) => timeline,
+        None => return,
+    };
+    let session: NodeId = match e.get(SESSION).and_then(|d| (*d).try_into().ok()) {
+        Some(session) => session,
+        None => return,
+    };
+    let endpoint = e.get(ENDPOINT).filter(|d| d.len() == 6).map(|d| {
+        SocketAddr::from((Ipv4Addr::new(d[0], d[1], d[2], d[3]), u16::from_be_bytes([d[4], d[5]])))
+    });
+
+    let peer = Peer {
+        session,
+        timeline,
+        expires: Instant::now() + Duration::from_secs(ttl as u64),
+    };
+
+    if state.peers.insert(node, peer).is_none() {
+        log::info!("Link: Found peer {}", String::from_utf8_lossy(&node));
+
+        if kind == ALIVE {
+            let _ = socket.send_to(&state.discovery(RESPONSE), from);
+        }
+    }
+
+    if session == state.session {
+        // Tempo changes re-anchor the timeline at the time of the change, so the latest one
+        // wins and a stale broadcast crossing ours in flight can't revert it
+        if timeline.time_origin > state.timeline.time_origin {
+            state.timeline = timeline;
+        }
+
+        if let Some(start_stop) = e.get(START_STOP).and_then(|d| StartStop::decode(d)) {
+            if start_stop.timestamp > state.start_stop.timestamp {
+                state.start_stop = start_stop;
+            }
+        }
+    } else if state.measure.is_none() && !state.others.contains(&session) {
+        // Measure the other session's ghost clock, join() decides which one to keep
+        if let Some(endpoint) = endpoint {
+            state.measure = Some(Measure {
+                session,
+                endpoint,
+                samples: Vec::with_capacity(SAMPLES),
+                sent: Instant::now(),
+            });
+            ping(state, socket);
+        }
+    }
+}
+
+fn measurement(state: &mut State, from: SocketAddr, data: &[u8], socket: &UdpSocket) {
+    if data.len() < 9 {
+        return;
+    }
+
+    let payload = &data[9..];
+
+    match data[8] {
+        PING => {
+            let mut buf = MEASUREMENT.to_vec();
+            buf.push(PONG);
+            entry(&mut buf, SESSION, &state.session);
+            entry(&mut buf, GHOST_TIME, &state.ghost().to_be_bytes());
+            // Echo the ping so the sender can match up its host time
+            buf.extend_from_slice(payload);
+
+            let _ = socket.send_to(&buf, from);
+        }
+        PONG => {
+            let e = entries(payload);
+            let (ht, gt) = match (e.get(HOST_TIME), e.get(GHOST_TIME)) {
+                (Some(ht), Some(gt)) if ht.len() == 8 && gt.len() == 8 => (i64_at(ht, 0), i64_at(gt, 0)),
+                _ => return,
+            };
+
+            let now = state.host();
+            let done = match &mut state.measure {
+                Some(m) if e.get(SESSION).map_or(false, |s| *s == &m.session[..]) => {
+                    // Assume the pong was sent half way through the round trip
+                    m.samples.push(gt - (ht + now) / 2);
+                    m.samples.len() >= SAMPLES
+                }
+                _ => return,
+            };
+
+            if done {
+                join(state);
+                let _ = socket.send_to(&state.discovery(ALIVE), (MULTICAST, PORT));
+            } else {
+                ping(state, socket);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn ping(state: &mut State, socket: &UdpSocket) {
+    let host = state.host();
+
+    if let Some(m) = &mut state.measure {
+        let mut buf = MEASUREMENT.to_vec();
+        buf.push(PING);
+        entry(&mut buf, HOST_TIME, &host.to_be_bytes());
+
+        m.sent = Instant::now();
+        let _ = socket.send_to(&buf, m.endpoint);
+    }
+}
+
+fn join(state: &mut State) {
+    let mut m = match state.measure.take() {
+        Some(m) => m,
+        None => return,
+    };
+
+    m.samples.sort();
+    let offset = m.samples[m.samples.len() / 2];
+
+    if !prefer(m.session, offset - state.offset, state.session) {
+        log::debug!("Link: Staying out of session {}", String::from_utf8_lossy(&m.session));
+        state.others.insert(m.session);
+        return;
+    }
+
+    state.offset = offset;
+    state.session = m.session;
+
+    if let Some(peer) = state.peers.values().find(|p| p.session == m.session) {
+        state.timeline = peer.timeline;
+    }
+
+    log::info!(
+        "Link: Joined session {} at {:.2} BPM",
+        String::from_utf8_lossy(&m.session),
+        state.timeline.bpm()
+    );
+}
+
+// Link's rule for which of two sessions everyone ends up in: the one whose ghost clock
+// is ahead, so the older session wins, or the lower id if they're about the same age.
+// `ahead` is the other session's ghost time minus ours
+fn prefer(other: NodeId, ahead: i64, ours: NodeId) -> bool {
+    ahead > SESSION_EPS || (ahead.abs() <= SESSION_EPS && other < ours)
+}
+
+fn entry(buf: &mut Vec<u8>, key: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(key);
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn entries(mut data: &[u8]) -> HashMap<[u8; 4], &[u8]> {
+    let mut map = HashMap::new();
+
+    while data.len() >= 8 {
+        let key: [u8; 4] = data[..4].try_into().unwrap();
+        let len = u32::from_be_bytes(data[4..8].try_into().unwrap()) as usize;
+
+        if data.len() < 8 + len {
+            break;
+        }
+
+        map.insert(key, &data[8..8 + len]);
+        data = &data[8 + len..];
+    }
+
+    map
+}
+
+fn i64_at(data: &[u8], i: usize) -> i64 {
+    i64::from_be_bytes(data[i..i + 8].try_into().unwrap())
+}
+
+// Shared with any other Link apps on this machine
+fn multicast() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::ipv4(), Type::dgram(), Some(Protocol::udp()))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, PORT)).into())?;
+
+    let socket = socket.into_udp_socket();
+    socket.join_multicast_v4(&MULTICAST, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(true)?;
+
+    Ok(socket)
+}
+
+// Address of the interface multicast goes out on
+fn local_ip() -> Ipv4Addr {
+    UdpSocket::bind(("0.0.0.0", 0))
+        .and_then(|s| {
+            s.connect((MULTICAST, PORT))?;
+            s.local_addr()
+        })
+        .ok()
+        .and_then(|addr| match addr.ip() {
+            IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+            _ => None,
+        })
+        .unwrap_or(Ipv4Addr::LOCALHOST)
+}
+
+// Link node ids are 8 printable characters
+fn node_id() -> NodeId {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(std::process::id());
+    let mut bits = hasher.finish();
+
+    let mut id = [0u8; 8];
+    for c in id.iter_mut() {
+        *c = b'a' + (bits % 26) as u8;
+        bits /= 26;
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_tempo() {
+        assert!(Timeline::new(0.0, 0).is_none());
+        assert!(Timeline::new(-120.0, 0).is_none());
+        assert!(Timeline::new(f32::NAN, 0).is_none());
+        assert!(Timeline::new(f32::INFINITY, 0).is_none());
+
+        let timeline = Timeline::new(120.0, 0).unwrap();
+        assert!(timeline.retempo(0.0, 1_000_000).is_none());
+        assert_eq!(timeline.retempo(60.0, 1_000_000).unwrap().beats(2_000_000), 3_000_000);
+    }
+
+    #[test]
+    fn older_session_wins() {
+        let (low, high) = (*b"aaaaaaaa", *b"bbbbbbbb");
+
+        // Well ahead wins whatever the ids
+        assert!(prefer(high, SESSION_EPS + 1, low));
+        assert!(!prefer(low, -SESSION_EPS - 1, high));
+        // About the same age, the lower id wins
+        assert!(prefer(low, SESSION_EPS, high));
+        assert!(prefer(low, -SESSION_EPS, high));
+        assert!(!prefer(high, 0, low));
+    }
+
+    // Two peers over the loopback multicast group, needs a network that allows it.
+    // Run with `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn two_peers_share_a_session() {
+        let wait = |done: &dyn Fn() -> bool| {
+            let start = Instant::now();
+            while !done() {
+                assert!(start.elapsed() < Duration::from_secs(5), "Link peers never synced");
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+
+        let mut a = Link::init(120.0);
+        thread::sleep(Duration::from_secs(1));
+        let b = Link::init(90.0);
+
+        // b's session is younger, so it joins a's
+        wait(&|| a.peers() == 1 && b.peers() == 1 && (b.bpm() - 120.0).abs() < 1e-3);
+        assert!((a.bpm() - 120.0).abs() < 1e-3);
+        assert!((a.beats() - b.beats()).abs() < 0.05);
+
+        a.set_bpm(100.0);
+        wait(&|| (b.bpm() - 100.0).abs() < 1e-3);
+        assert!((a.beats() - b.beats()).abs() < 0.05);
+
+        a.set_playing(true);
+        wait(&|| b.playing());
+    }
+}
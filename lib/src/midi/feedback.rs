@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use super::message::{Midi, MidiBank, MidiOut};
+use super::Device;
+use crate::time::Decay;
+
+// Mirrors state onto the controller's LEDs, only sending what changed
+pub struct Feedback {
+    state: HashMap<(u8, u8), u8>,
+    sent: HashMap<(u8, u8), u8>,
+    flashes: HashMap<(u8, u8), (u8, Decay)>,
+}
+
+impl Feedback {
+    // Light a control to reflect a value, e.g. `Midi::CtrlButton(5, true)`
+    pub fn set(&mut self, bank: MidiBank, msg: Midi) {
+        if let Some(MidiOut::Cc(ch, cc, v)) = MidiOut::encode(bank, msg) {
+            self.state.insert((ch, cc), v);
+        }
+    }
+
+    // Light a control for `t` ms, then fall back to its set state
+    pub fn flash(&mut self, bank: MidiBank, msg: Midi, t: f32) {
+        if let Some(MidiOut::Cc(ch, cc, v)) = MidiOut::encode(bank, msg) {
+            let mut decay = Decay::new(t);
+            decay.set();
+            self.flashes.insert((ch, cc), (v, decay));
+        }
+    }
+
+    // Forget what the controller shows so everything is resent, e.g. after a reconnect
+    pub fn refresh(&mut self) {
+        self.sent.clear();
+    }
+
+    pub fn update(&mut self, delta: f32, midi: &mut Device) {
+        self.flashes.values_mut().for_each(|(_, decay)| decay.update(delta));
+        self.flashes.retain(|_, (_, decay)| !decay.off());
+
+        let current = self
+            .state
+            .iter()
+            .map(|(k, v)| (*k, *v))
+            .chain(self.flashes.iter().map(|(k, (v, _))| (*k, *v)))
+            .collect::<HashMap<_, _>>();
+
+        for (&(ch, cc), &v) in &current {
+            if self.sent.get(&(ch, cc)) != Some(&v) {
+                midi.send(&MidiOut::Cc(ch, cc, v));
+                self.sent.insert((ch, cc), v);
+            }
+        }
+
+        // Controls that stopped flashing and were never set go dark
+        let stale = self
+            .sent
+            .keys()
+            .filter(|k| !current.contains_key(k))
+            .copied()
+            .collect::<Vec<_>>();
+        for (ch, cc) in stale {
+            midi.send(&MidiOut::Cc(ch, cc, 0));
+            self.sent.remove(&(ch, cc));
+        }
+    }
+}
+
+impl Default for Feedback {
+    fn default() -> Self {
+        Self {
+            state: HashMap::new(),
+            sent: HashMap::new(),
+            flashes: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_changes() {
+        let (mut device, handle) = Device::memory();
+        let mut feedback = Feedback::default();
+
+        feedback.set(MidiBank::B1, Midi::CtrlButton(2, true));
+        feedback.update(0.016, &mut device);
+        let sent = handle.sent_midi();
+        assert_eq!(sent.len(), 1);
+        assert!(matches!(sent[0], (MidiBank::B1, Midi::CtrlButton(2, true))));
+
+        // Nothing changed, nothing sent
+        feedback.update(0.016, &mut device);
+        assert!(handle.sent().is_empty());
+
+        feedback.set(MidiBank::B1, Midi::CtrlButton(2, false));
+        feedback.update(0.016, &mut device);
+        let sent = handle.sent_midi();
+        assert!(matches!(sent[0], (MidiBank::B1, Midi::CtrlButton(2, false))));
+
+        // Everything again after a refresh
+        feedback.refresh();
+        feedback.update(0.016, &mut device);
+        assert_eq!(handle.sent().len(), 1);
+    }
+
+    #[test]
+    fn flashes_go_dark() {
+        let (mut device, handle) = Device::memory();
+        let mut feedback = Feedback::default();
+
+        feedback.flash(MidiBank::B0, Midi::MainButton(1, true), 100.0);
+        feedback.update(0.016, &mut device);
+        assert!(matches!(handle.sent_midi()[..], [(MidiBank::B0, Midi::MainButton(1, true))]));
+
+        feedback.update(0.2, &mut device);
+        assert!(matches!(handle.sent_midi()[..], [(MidiBank::B0, Midi::MainButton(1, false))]));
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::Arc;
+
+use crossbeam_queue::ArrayQueue;
+
+use super::message::MidiRaw;
+use super::transport::Transport;
+use crate::audio::Audio;
+
+// The `midi` and `midi_out` ports on the audio client
+pub struct Jack {
+    rx: Arc<ArrayQueue<(u64, MidiRaw)>>,
+    tx: Arc<ArrayQueue<Vec<u8>>>,
+}
+
+impl Jack {
+    pub fn new(audio: &Audio) -> Self {
+        let (rx, tx) = audio.midi_queues();
+        Self { rx, tx }
+    }
+}
+
+impl Transport for Jack {
+    fn recv(&mut self) -> Vec<(u64, MidiRaw)> {
+        let mut messages = Vec::new();
+
+        while let Some(m) = self.rx.pop() {
+            messages.push(m);
+        }
+
+        messages
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        if self.tx.push(bytes.to_vec()).is_err() {
+            log::warn!("MIDI output queue full, dropping message");
+        }
+    }
+}
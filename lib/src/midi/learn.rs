@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::message::{Midi, MidiBank};
+use super::takeover::Takeover;
+use crate::param::Params;
+
+// A physical control on the controller, independent of its current value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Control {
+    TopButton(u8),
+    MainButton(u8),
+    CtrlButton(u8),
+    BankButton(u8),
+    Slider(u8),
+    Knob(u8),
+    Fader,
+    Encoder,
+    Relative(u8),
+    HighRes(u8),
+    Nrpn(u16),
+    Rpn(u16),
+}
+
+impl Control {
+    // Split a message into the control it came from and its normalized value.
+    // Encoders are relative and report their step direction instead
+    pub fn of(msg: Midi) -> Option<(Control, f32)> {
+        let on = |b: bool| if b { 1.0 } else { 0.0 };
+
+        Some(match msg {
+            Midi::TopButton(i, b) => (Control::TopButton(i), on(b)),
+            Midi::MainButton(i, b) => (Control::MainButton(i), on(b)),
+            Midi::CtrlButton(i, b) => (Control::CtrlButton(i), on(b)),
+            Midi::BankButton(i, b) => (Control::BankButton(i), on(b)),
+            Midi::Slider(i, f) => (Control::Slider(i), f.min(1.0)),
+            Midi::Knob(i, f) => (Control::Knob(i), f.min(1.0)),
+            Midi::Fader(f) => (Control::Fader, f.min(1.0)),
+            Midi::Encoder(d) => (Control::Encoder, d as f32),
+            Midi::Relative(cc, d) => (Control::Relative(cc), d as f32),
+            Midi::HighRes(cc, f) => (Control::HighRes(cc), f),
+            Midi::Nrpn(n, f) => (Control::Nrpn(n), f),
+            Midi::Rpn(n, f) => (Control::Rpn(n), f),
+            _ => return None,
+        })
+    }
+
+    pub fn relative(&self) -> bool {
+        matches!(self, Control::Encoder | Control::Relative(_))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Binding {
+    bank: MidiBank,
+    control: Control,
+    param: String,
+}
+
+// Binds physical controls to named parameters by arming a parameter and moving a control
+pub struct Learn {
+    pub takeover: Takeover,
+    bindings: HashMap<(MidiBank, Control), String>,
+    armed: Option<String>,
+}
+
+impl Learn {
+    // Fraction of a parameter's range moved by one encoder detent
+    const ENCODER_STEP: f32 = 1.0 / 32.0;
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let bindings: Vec<Binding> = serde_json::from_slice(&data)?;
+
+        Ok(Self {
+            bindings: bindings
+                .into_iter()
+                .map(|b| ((b.bank, b.control), b.param))
+                .collect(),
+            takeover: Takeover::default(),
+            armed: None,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let bindings = self
+            .bindings
+            .iter()
+            .map(|(&(bank, control), param)| Binding {
+                bank,
+                control,
+                param: param.clone(),
+            })
+            .collect::<Vec<_>>();
+
+        let data = serde_json::to_vec_pretty(&bindings)?;
+        std::fs::write(path, data)
+    }
+
+    // The next control moved gets bound to `param`
+    pub fn arm(&mut self, param: &str) {
+        log::info!("MIDI learn: move a control to bind '{}'", param);
+        self.armed = Some(param.to_string());
+    }
+
+    pub fn disarm(&mut self) {
+        self.armed = None;
+    }
+
+    pub fn armed(&self) -> Option<&str> {
+        self.armed.as_deref()
+    }
+
+    pub fn bind(&mut self, bank: MidiBank, control: Control, param: &str) {
+        // A parameter is driven by one control at a time
+        self.unbind(param);
+        self.bindings.insert((bank, control), param.to_string());
+    }
+
+    pub fn unbind(&mut self, param: &str) {
+        self.bindings.retain(|_, p| p != param);
+    }
+
+    pub fn binding(&self, bank: MidiBank, control: Control) -> Option<&str> {
+        self.bindings.get(&(bank, control)).map(String::as_str)
+    }
+
+    // Learn or apply a message. Returns true if it was bound to a parameter
+    pub fn midi(&mut self, bank: MidiBank, msg: Midi, params: &mut Params) -> bool {
+        let (control, f) = match Control::of(msg) {
+            Some(c) => c,
+            None => return false,
+        };
+
+        if let Some(param) = self.armed.take() {
+            log::info!("MIDI learn: bound {:?} {:?} to '{}'", bank, control, param);
+            self.bind(bank, control, &param);
+        }
+
+        let param = match self.bindings.get(&(bank, control)) {
+            Some(name) => name,
+            None => return false,
+        };
+
+        match params.get_mut(param) {
+            Some(p) if control.relative() => {
+                let f = p.normalized() + f * Self::ENCODER_STEP;
+                p.set_normalized(f.max(0.0).min(1.0));
+            }
+            Some(p) => {
+                if let Some(f) = self.takeover.process(bank, control, f, p.normalized()) {
+                    p.set_normalized(f);
+                }
+            }
+            None => log::warn!("MIDI learn: unknown parameter '{}'", param),
+        }
+
+        true
+    }
+}
+
+impl Default for Learn {
+    fn default() -> Self {
+        Self {
+            takeover: Takeover::default(),
+            bindings: HashMap::new(),
+            armed: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::Pickup;
+    use crate::param::Param;
+
+    fn learn() -> Learn {
+        let mut learn = Learn::default();
+        learn.takeover.default = Pickup::Jump;
+        learn
+    }
+
+    #[test]
+    fn binds_armed_parameter() {
+        let mut params = Params::default().with("glitch", Param::toggle(false));
+        let mut learn = learn();
+
+        learn.arm("glitch");
+        assert!(learn.midi(MidiBank::B2, Midi::CtrlButton(3, true), &mut params));
+
+        assert_eq!(learn.armed(), None);
+        assert_eq!(learn.binding(MidiBank::B2, Control::CtrlButton(3)), Some("glitch"));
+        assert!(params.bool("glitch"));
+    }
+
+    #[test]
+    fn bindings_are_per_bank() {
+        let mut params = Params::default().with("edge", Param::new(0.0, 0.0, 1.0));
+        let mut learn = learn();
+        learn.bind(MidiBank::B1, Control::Slider(0), "edge");
+
+        assert!(!learn.midi(MidiBank::B0, Midi::Slider(0, 0.9), &mut params));
+        assert_eq!(params.f32("edge"), 0.0);
+        assert!(learn.midi(MidiBank::B1, Midi::Slider(0, 0.5), &mut params));
+        assert!((params.f32("edge") - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn one_control_per_parameter() {
+        let mut learn = learn();
+        learn.bind(MidiBank::B0, Control::Knob(0), "edge");
+        learn.bind(MidiBank::B0, Control::Knob(1), "edge");
+
+        assert_eq!(learn.binding(MidiBank::B0, Control::Knob(0)), None);
+        assert_eq!(learn.binding(MidiBank::B0, Control::Knob(1)), Some("edge"));
+    }
+
+    #[test]
+    fn encoders_step() {
+        let mut params = Params::default().with("zoom", Param::new(0.5, 0.0, 1.0));
+        let mut learn = learn();
+        learn.bind(MidiBank::B0, Control::Encoder, "zoom");
+
+        learn.midi(MidiBank::B0, Midi::Encoder(2), &mut params);
+        assert!((params.f32("zoom") - (0.5 + 2.0 * Learn::ENCODER_STEP)).abs() < 1e-6);
+        learn.midi(MidiBank::B0, Midi::Encoder(-127), &mut params);
+        assert_eq!(params.f32("zoom"), 0.0);
+    }
+
+    #[test]
+    fn waits_for_pickup() {
+        let mut params = Params::default().with("edge", Param::new(0.0, 0.0, 1.0));
+        let mut learn = Learn::default();
+        learn.bind(MidiBank::B1, Control::Slider(0), "edge");
+
+        learn.midi(MidiBank::B1, Midi::Slider(0, 0.0), &mut params);
+        learn.midi(MidiBank::B1, Midi::Slider(0, 0.5), &mut params);
+        assert!((params.f32("edge") - 0.5).abs() < 1e-6);
+
+        // Changed elsewhere, the slider has to come back to the value first
+        params.set("edge", 1.0);
+        learn.midi(MidiBank::B1, Midi::Slider(0, 0.6), &mut params);
+        assert_eq!(params.f32("edge"), 1.0);
+        learn.midi(MidiBank::B1, Midi::Slider(0, 1.0), &mut params);
+        learn.midi(MidiBank::B1, Midi::Slider(0, 0.75), &mut params);
+        assert!((params.f32("edge") - 0.75).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,335 @@
+use crossbeam_queue::SegQueue;
+use std::ops::Range;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Arc,
+};
+
+use super::message::{self, Midi, MidiBank, MidiOut, MidiRaw, MidiState};
+use super::transport::Transport;
+
+struct Shared {
+    rx: SegQueue<(u64, MidiRaw)>,
+    tx: SegQueue<Vec<u8>>,
+    // Route sent messages straight back into the input
+    loopback: AtomicBool,
+    // Bank the device was last told about. The controller only reports banks
+    // through sysex, so messages on another bank need one first
+    bank: AtomicU8,
+}
+
+impl Shared {
+    fn push(&self, stamp: u64, bytes: &[u8]) {
+        if let (Some(0xF0), Some(&b)) = (bytes.first(), bytes.get(9)) {
+            if b < 4 {
+                self.bank.store(b, Ordering::SeqCst);
+            }
+        }
+
+        self.rx.push((stamp, message::raw_from(bytes)));
+    }
+
+    // Switch banks before a message that carries its bank as the channel
+    fn push_bank(&self, stamp: u64, bank: MidiBank) {
+        if self.bank.load(Ordering::SeqCst) != bank as u8 {
+            self.push(stamp, &MidiOut::bank(bank).bytes());
+        }
+    }
+}
+
+// In-process transport, fed and observed through a MemoryHandle.
+// Lets MIDI handling run without hardware or a JACK server
+pub struct Memory {
+    shared: Arc<Shared>,
+}
+
+#[derive(Clone)]
+pub struct MemoryHandle {
+    shared: Arc<Shared>,
+}
+
+impl Memory {
+    // Sent messages come back as input, like a cable from the output to the input
+    pub fn loopback() -> Self {
+        let memory = Self::default();
+        memory.shared.loopback.store(true, Ordering::SeqCst);
+        memory
+    }
+
+    pub fn handle(&self) -> MemoryHandle {
+        MemoryHandle {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl MemoryHandle {
+    // Deliver a message as if it arrived from a device
+    pub fn feed(&self, stamp: u64, bytes: &[u8]) {
+        self.shared.push(stamp, bytes);
+    }
+
+    // Deliver a controller event, encoded the way the hardware would send it
+    pub fn feed_midi(&self, stamp: u64, bank: MidiBank, msg: Midi) {
+        match msg {
+            Midi::Clock => self.feed(stamp, &[248]),
+            Midi::Start => self.feed(stamp, &[250]),
+            Midi::Continue => self.feed(stamp, &[251]),
+            Midi::Stop => self.feed(stamp, &[252]),
+            Midi::Bank(b) if b < 4 => self.feed(stamp, &MidiOut::bank(bank_of(b)).bytes()),
+            _ => match MidiOut::encode(bank, msg) {
+                Some(out) => {
+                    self.shared.push_bank(stamp, bank);
+                    self.feed(stamp, &out.bytes());
+                }
+                None => log::warn!("Can't feed {:?}, it has no wire encoding", msg),
+            },
+        }
+    }
+
+    // Feed `n` beats of MIDI clock at `bpm`, starting at `stamp`. Returns the stamp after the last tick
+    pub fn feed_clock(&self, stamp: u64, bpm: f32, n: u32) -> u64 {
+        let tick = (60_000_000.0 / bpm / 24.0) as u64;
+
+        for i in 0..n as u64 * 24 {
+            self.feed(stamp + i * tick, &[248]);
+        }
+
+        stamp + n as u64 * 24 * tick
+    }
+
+    pub fn set_loopback(&self, on: bool) {
+        self.shared.loopback.store(on, Ordering::SeqCst);
+    }
+
+    // Raw messages the device sent since the last call
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        let mut messages = Vec::with_capacity(self.shared.tx.len());
+
+        while let Some(m) = self.shared.tx.pop() {
+            messages.push(m);
+        }
+
+        messages
+    }
+
+    // Sent messages decoded as controller events, e.g. to check LED feedback
+    pub fn sent_midi(&self) -> Vec<(MidiBank, Midi)> {
+        let mut state = MidiState::default();
+
+        self.sent()
+            .iter()
+            .map(|bytes| {
+                // The output encodes banks as channels rather than sysex
+                let bank = bank_of(bytes.first().map_or(0, |b| b & 0x0F));
+                (bank, state.process(message::raw_from(bytes)).1)
+            })
+            .collect()
+    }
+}
+
+impl Transport for Memory {
+    fn recv(&mut self) -> Vec<(u64, MidiRaw)> {
+        let mut messages = Vec::with_capacity(self.shared.rx.len());
+
+        while let Some(m) = self.shared.rx.pop() {
+            messages.push(m);
+        }
+
+        messages
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        if self.shared.loopback.load(Ordering::SeqCst) {
+            // Sent controls carry their bank as the channel, read back it needs the sysex
+            if let Some(&status @ 0xB0..=0xB3) = bytes.first() {
+                self.shared.push_bank(0, bank_of(status & 0x0F));
+            }
+            self.shared.push(0, bytes);
+        } else {
+            self.shared.tx.push(bytes.to_vec());
+        }
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                rx: SegQueue::new(),
+                tx: SegQueue::new(),
+                loopback: AtomicBool::new(false),
+                bank: AtomicU8::new(MidiBank::B0 as u8),
+            }),
+        }
+    }
+}
+
+fn bank_of(b: u8) -> MidiBank {
+    match b {
+        1 => MidiBank::B1,
+        2 => MidiBank::B2,
+        3 => MidiBank::B3,
+        _ => MidiBank::B0,
+    }
+}
+
+// A timed sequence of events fed through a MemoryHandle, for scripted demos
+pub struct Script {
+    events: Vec<(f32, MidiBank, Midi)>,
+    t: f32,
+    i: usize,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self {
+            events: Vec::new(),
+            t: 0.0,
+            i: 0,
+        }
+    }
+
+    // Send `msg` `t` seconds after the script starts
+    pub fn at(mut self, t: f32, bank: MidiBank, msg: Midi) -> Self {
+        let i = self.events.iter().position(|e| e.0 > t).unwrap_or(self.events.len());
+        self.events.insert(i, (t, bank, msg));
+        self
+    }
+
+    // Sweep a continuous control across the values `f` over the times `t` in `n` steps,
+    // e.g. ramp(1.0..3.0, 0.0..1.0, 16, MidiBank::B0, |f| Midi::Knob(0, f))
+    pub fn ramp(mut self, t: Range<f32>, f: Range<f32>, n: usize, bank: MidiBank, msg: fn(f32) -> Midi) -> Self {
+        for i in 0..=n {
+            let fr = i as f32 / n.max(1) as f32;
+            self = self.at(t.start + (t.end - t.start) * fr, bank, msg(f.start + (f.end - f.start) * fr));
+        }
+        self
+    }
+
+    pub fn done(&self) -> bool {
+        self.i >= self.events.len()
+    }
+
+    pub fn update(&mut self, delta: f32, handle: &MemoryHandle) {
+        self.t += delta;
+
+        while let Some(&(t, bank, msg)) = self.events.get(self.i) {
+            if t > self.t {
+                break;
+            }
+
+            handle.feed_midi((t * 1_000_000.0) as u64, bank, msg);
+            self.i += 1;
+        }
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::Device;
+
+    // Everything polled, leaving out the bank switches
+    fn poll(device: &mut Device) -> Vec<(MidiBank, Midi)> {
+        device
+            .poll()
+            .into_iter()
+            .filter(|(_, msg)| !matches!(msg, Midi::Bank(_)))
+            .collect()
+    }
+
+    #[test]
+    fn banks_round_trip() {
+        let (mut device, handle) = Device::memory();
+
+        handle.feed_midi(0, MidiBank::B2, Midi::Slider(3, 0.5));
+        handle.feed_midi(1, MidiBank::B2, Midi::Knob(1, 1.0));
+        handle.feed_midi(2, MidiBank::B0, Midi::CtrlButton(0, true));
+        handle.feed_midi(3, MidiBank::B3, Midi::Fader(0.0));
+
+        let messages = device.poll();
+        assert_eq!(messages.len(), 7);
+        assert!(matches!(messages[0], (MidiBank::B2, Midi::Bank(2))));
+        assert!(matches!(messages[1], (MidiBank::B2, Midi::Slider(3, f)) if (f - 0.5).abs() < 1e-6));
+        assert!(matches!(messages[2], (MidiBank::B2, Midi::Knob(1, f)) if f == 1.0));
+        assert!(matches!(messages[3], (MidiBank::B0, Midi::Bank(0))));
+        assert!(matches!(messages[4], (MidiBank::B0, Midi::CtrlButton(0, true))));
+        assert!(matches!(messages[5], (MidiBank::B3, Midi::Bank(3))));
+        assert!(matches!(messages[6], (MidiBank::B3, Midi::Fader(f)) if f == 0.0));
+    }
+
+    #[test]
+    fn loopback_keeps_banks() {
+        let memory = Memory::loopback();
+        let mut device = Device::new(memory);
+
+        device.send(&MidiOut::encode(MidiBank::B1, Midi::CtrlButton(2, true)).unwrap());
+        device.send(&MidiOut::encode(MidiBank::B0, Midi::Knob(0, 1.0)).unwrap());
+
+        let messages = poll(&mut device);
+        assert!(matches!(messages[0], (MidiBank::B1, Midi::CtrlButton(2, true))));
+        assert!(matches!(messages[1], (MidiBank::B0, Midi::Knob(0, _))));
+    }
+
+    #[test]
+    fn script_replays_banks() {
+        let (mut device, handle) = Device::memory();
+        let mut script = Script::new()
+            .at(0.0, MidiBank::B3, Midi::Knob(0, 1.0))
+            .at(0.5, MidiBank::B1, Midi::MainButton(4, true));
+
+        script.update(0.1, &handle);
+        let messages = poll(&mut device);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0], (MidiBank::B3, Midi::Knob(0, _))));
+
+        script.update(0.5, &handle);
+        let messages = poll(&mut device);
+        assert!(matches!(messages[0], (MidiBank::B1, Midi::MainButton(4, true))));
+        assert!(script.done());
+    }
+
+    #[test]
+    fn script_ramps() {
+        let (mut device, handle) = Device::memory();
+        let mut script = Script::new().ramp(1.0..2.0, 0.0..1.0, 4, MidiBank::B1, |f| Midi::Knob(2, f));
+
+        script.update(1.5, &handle);
+        let values = poll(&mut device)
+            .into_iter()
+            .map(|(bank, msg)| match msg {
+                Midi::Knob(2, f) if bank == MidiBank::B1 => (f * 4.0).round() as u8,
+                _ => panic!("Unexpected {:?}", msg),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![0, 1, 2]);
+
+        script.update(0.5, &handle);
+        assert_eq!(poll(&mut device).len(), 2);
+        assert!(script.done());
+    }
+
+    #[test]
+    fn clock_through_device() {
+        let (mut device, handle) = Device::memory();
+
+        handle.feed_midi(0, MidiBank::B0, Midi::Start);
+        handle.feed_clock(0, 120.0, 2);
+        device.poll();
+
+        assert!(device.clock.running());
+        assert!((device.clock.bpm().unwrap() - 120.0).abs() < 0.1);
+        assert!((device.clock.beats() - 47.0 / 24.0).abs() < 1e-6);
+
+        handle.feed_midi(0, MidiBank::B0, Midi::Stop);
+        device.poll();
+        assert!(!device.clock.running());
+    }
+}
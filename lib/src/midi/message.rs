@@ -0,0 +1,438 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Midi {
+    TopButton(u8, bool),
+    MainButton(u8, bool),
+    CtrlButton(u8, bool),
+    BankButton(u8, bool),
+    Slider(u8, f32),
+    Knob(u8, f32),
+    Fader(f32),
+    Encoder(i8),
+    Relative(u8, i8),
+    HighRes(u8, f32),
+    Nrpn(u16, f32),
+    Rpn(u16, f32),
+    Bank(u8),
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MidiBank {
+    B0,
+    B1,
+    B2,
+    B3,
+}
+
+#[derive(Debug, Clone)]
+pub enum MidiOut {
+    NoteOn(u8, u8, u8),
+    NoteOff(u8, u8),
+    Cc(u8, u8, u8),
+    Sysex(Vec<u8>),
+}
+
+impl MidiOut {
+    pub fn bytes(&self) -> Vec<u8> {
+        match self {
+            MidiOut::NoteOn(ch, note, vel) => vec![0x90 | (ch & 0xF), *note & 0x7F, *vel & 0x7F],
+            MidiOut::NoteOff(ch, note) => vec![0x80 | (ch & 0xF), *note & 0x7F, 0],
+            MidiOut::Cc(ch, cc, v) => vec![0xB0 | (ch & 0xF), *cc & 0x7F, *v & 0x7F],
+            MidiOut::Sysex(data) => {
+                let mut bytes = Vec::with_capacity(data.len() + 2);
+                bytes.push(0xF0);
+                bytes.extend(data.iter().map(|b| b & 0x7F));
+                bytes.push(0xF7);
+                bytes
+            }
+        }
+    }
+
+    // The scene change sysex the controller sends when switching banks, decoded as Midi::Bank
+    pub fn bank(bank: MidiBank) -> MidiOut {
+        MidiOut::Sysex(vec![0x42, 0x40, 0x00, 0x01, 0x04, 0x00, 0x5F, 0x4F, bank as u8])
+    }
+
+    // Inverse of MidiState::process, for driving the controller's LEDs and motors
+    pub fn encode(bank: MidiBank, msg: Midi) -> Option<MidiOut> {
+        let ch = bank as u8;
+        let on = |b: bool| if b { 127 } else { 0 };
+        let fl = |f: f32| (f.max(0.0).min(1.0) * 126.0).round() as u8;
+
+        let (cc, v) = match msg {
+            Midi::BankButton(i, b) => (1 + i, on(b)),
+            Midi::Fader(f) => (9, fl(f) + 1),
+            Midi::Knob(i, f) => (14 + i, fl(f)),
+            Midi::MainButton(i, b) => (23 + i, on(b)),
+            Midi::Slider(i, f) => (32 + i, fl(f)),
+            Midi::CtrlButton(i, b) => (44 + i, on(b)),
+            Midi::TopButton(0, b) => (67, on(b)),
+            Midi::TopButton(1, b) => (64, on(b)),
+            _ => return None,
+        };
+
+        Some(MidiOut::Cc(ch, cc, v))
+    }
+}
+
+// How a relative encoder packs its step into a CC value
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EncoderMode {
+    // 1..63 up, 127..65 down
+    TwosComplement,
+    // 65..127 up, 63..0 down
+    Offset64,
+    // 1..63 up, 65..127 down
+    SignMagnitude,
+}
+
+impl EncoderMode {
+    pub fn decode(&self, v: u8) -> i8 {
+        let v = v & 0x7F;
+        match self {
+            EncoderMode::TwosComplement => {
+                if v < 64 {
+                    v as i8
+                } else {
+                    (v as i16 - 128) as i8
+                }
+            }
+            EncoderMode::Offset64 => v as i8 - 64,
+            EncoderMode::SignMagnitude => {
+                if v & 0x40 != 0 {
+                    -((v & 0x3F) as i8)
+                } else {
+                    v as i8
+                }
+            }
+        }
+    }
+}
+
+pub type MidiRaw = [u8; 16];
+
+// Length of the message at the start of a zero padded raw buffer
+pub fn raw_len(raw: &MidiRaw) -> usize {
+    match raw[0] {
+        0x80..=0xBF | 0xE0..=0xEF | 0xF2 => 3,
+        0xC0..=0xDF | 0xF1 | 0xF3 => 2,
+        0xF0 => raw.iter().position(|&b| b == 0xF7).map_or(raw.len(), |i| i + 1),
+        _ => 1,
+    }
+}
+
+pub fn raw_from(bytes: &[u8]) -> MidiRaw {
+    let mut raw = [0; 16];
+    for (i, b) in bytes.iter().take(16).enumerate() {
+        raw[i] = *b;
+    }
+    raw
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Parameter {
+    Nrpn(u16),
+    Rpn(u16),
+}
+
+pub(crate) struct MidiState {
+    // Last program number from the encoder, None until the first one
+    encoder: Option<u8>,
+    bank: MidiBank,
+    relative: HashMap<u8, EncoderMode>,
+
+    // 14 bit CC pairs, keyed by MSB controller number
+    high_res: HashSet<u8>,
+    msb: [u8; 32],
+
+    // (N)RPN parameter selection and data entry
+    nrpn: bool,
+    select: u8,
+    parameter: Option<Parameter>,
+    data: HashMap<Parameter, u16>,
+}
+
+impl Default for MidiState {
+    fn default() -> Self {
+        MidiState {
+            encoder: None,
+            bank: MidiBank::B0,
+            relative: HashMap::new(),
+
+            high_res: HashSet::new(),
+            msb: [0; 32],
+
+            nrpn: false,
+            select: 0,
+            parameter: None,
+            data: HashMap::new(),
+        }
+    }
+}
+
+impl MidiState {
+    const MAX_14: f32 = 16383.0;
+
+    // Treat a CC as a relative encoder, reported as Midi::Relative instead
+    pub fn relative(&mut self, cc: u8, mode: EncoderMode) {
+        self.relative.insert(cc, mode);
+    }
+
+    // Pair CC `msb` (0-31) with `msb + 32` as a 14 bit value, reported as Midi::HighRes
+    pub fn high_res(&mut self, msb: u8) {
+        assert!(msb < 32, "14 bit CCs use controllers 0-31 as the MSB");
+        self.high_res.insert(msb);
+    }
+
+    // Decode NRPN/RPN sequences on CCs 6, 38 and 96-101 instead of as controls
+    pub fn nrpn(&mut self, enable: bool) {
+        self.nrpn = enable;
+    }
+
+    fn cc14(&mut self, cc: u8, v: u8) -> Option<Midi> {
+        match cc {
+            // A new MSB resets the LSB, so devices that only send MSBs still work
+            0..=31 if self.high_res.contains(&cc) => {
+                self.msb[cc as usize] = v;
+                Some(Midi::HighRes(cc, ((v as u16) << 7) as f32 / Self::MAX_14))
+            }
+            32..=63 if self.high_res.contains(&(cc - 32)) => {
+                let msb = self.msb[(cc - 32) as usize] as u16;
+                let v = (msb << 7) | v as u16;
+                Some(Midi::HighRes(cc - 32, v as f32 / Self::MAX_14))
+            }
+            _ => None,
+        }
+    }
+
+    fn parameter(&mut self, cc: u8, v: u8) -> Option<Midi> {
+        let value = |p: Parameter, v: u16| {
+            let f = v.min(16383) as f32 / Self::MAX_14;
+            match p {
+                Parameter::Nrpn(n) => Midi::Nrpn(n, f),
+                Parameter::Rpn(n) => Midi::Rpn(n, f),
+            }
+        };
+
+        match cc {
+            99 | 101 => self.select = v,
+            98 => self.parameter = Some(Parameter::Nrpn(((self.select as u16) << 7) | v as u16)),
+            100 => {
+                // RPN 127/127 is the null parameter, closing the sequence
+                self.parameter = match (self.select, v) {
+                    (127, 127) => None,
+                    (msb, lsb) => Some(Parameter::Rpn(((msb as u16) << 7) | lsb as u16)),
+                }
+            }
+            6 | 38 | 96 | 97 => {
+                let p = self.parameter?;
+                let data = self.data.entry(p).or_insert(0);
+                *data = match cc {
+                    // A new MSB resets the LSB, so 7 bit devices still work
+                    6 => (v as u16) << 7,
+                    38 => (*data & !0x7F) | v as u16,
+                    96 => (*data + 1).min(16383),
+                    _ => data.saturating_sub(1),
+                };
+                return Some(value(p, *data));
+            }
+            _ => return None,
+        }
+
+        Some(Midi::Unknown)
+    }
+
+    pub fn process(&mut self, raw: MidiRaw) -> (MidiBank, Midi) {
+        let message = match raw[0] {
+            176..=179 => {
+                let cc = raw[1];
+                let state = raw[2];
+                let on = state == 127;
+                let fl = state as f32 / 126.0;
+
+                if let Some(mode) = self.relative.get(&cc) {
+                    return (self.bank, Midi::Relative(cc, mode.decode(state)));
+                }
+
+                if let Some(msg) = self.cc14(cc, state) {
+                    return (self.bank, msg);
+                }
+
+                if self.nrpn {
+                    if let Some(msg) = self.parameter(cc, state) {
+                        return (self.bank, msg);
+                    }
+                }
+
+                match cc {
+                    1..=2 => Midi::BankButton(cc - 1, on),
+                    9 => Midi::Fader((std::cmp::max(state, 1) - 1) as f32 / 126.0),
+                    14..=22 => Midi::Knob(cc - 14, fl),
+                    23..=31 => Midi::MainButton(cc - 23, on),
+                    32..=40 => Midi::Slider(cc - 32, fl),
+                    44..=49 => Midi::CtrlButton(cc - 44, on),
+                    67 => Midi::TopButton(0, on),
+                    64 => Midi::TopButton(1, on),
+                    _ => Midi::Unknown,
+                    // _ => Midi::Unknown(Vec::from(raw)),
+                }
+            }
+            192 => {
+                // The encoder sends absolute program numbers, so take the shortest
+                // distance around the wrap. Devices that clamp repeat 0 or 127 at the ends.
+                // The first one only tells us where the encoder starts
+                let v = raw[1] & 0x7F;
+                let d = match (self.encoder.replace(v), v) {
+                    (None, _) => return (self.bank, Midi::Unknown),
+                    (Some(0), 0) => -1,
+                    (Some(127), 127) => 1,
+                    (Some(prev), v) => ((v as i16 - prev as i16 + 64).rem_euclid(128) - 64) as i8,
+                };
+                Midi::Encoder(d)
+            }
+            240 => {
+                let b = raw[9];
+                let bank = match b {
+                    0 => MidiBank::B0,
+                    1 => MidiBank::B1,
+                    2 => MidiBank::B2,
+                    3 => MidiBank::B3,
+                    _ => {
+                        log::debug!("Ignoring sysex {:?}", &raw[..raw_len(&raw)]);
+                        return (self.bank, Midi::Unknown);
+                    }
+                };
+                self.bank = bank;
+                Midi::Bank(b)
+            }
+            248 => Midi::Clock,
+            250 => Midi::Start,
+            251 => Midi::Continue,
+            252 => Midi::Stop,
+            _ => Midi::Unknown,
+            // _ => Midi::Unknown(Vec::from(raw)),
+        };
+
+        (self.bank, message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: [u8; 5] = [1, 63, 64, 65, 127];
+
+    fn decode(mode: EncoderMode) -> Vec<i8> {
+        VALUES.iter().map(|&v| mode.decode(v)).collect()
+    }
+
+    fn program(state: &mut MidiState, v: u8) -> Midi {
+        state.process(raw_from(&[0xC0, v])).1
+    }
+
+    #[test]
+    fn twos_complement() {
+        assert_eq!(decode(EncoderMode::TwosComplement), vec![1, 63, -64, -63, -1]);
+    }
+
+    #[test]
+    fn offset_64() {
+        assert_eq!(decode(EncoderMode::Offset64), vec![-63, -1, 0, 1, 63]);
+    }
+
+    #[test]
+    fn sign_magnitude() {
+        assert_eq!(decode(EncoderMode::SignMagnitude), vec![1, 63, 0, -1, -63]);
+    }
+
+    fn cc(state: &mut MidiState, cc: u8, v: u8) -> Midi {
+        state.process(raw_from(&[0xB0, cc, v])).1
+    }
+
+    fn close(a: f32, b: u16) -> bool {
+        (a - b as f32 / MidiState::MAX_14).abs() < 1e-6
+    }
+
+    #[test]
+    fn high_res_pairs() {
+        let mut state = MidiState::default();
+        state.high_res(7);
+
+        assert!(matches!(cc(&mut state, 7, 64), Midi::HighRes(7, f) if close(f, 64 << 7)));
+        assert!(matches!(cc(&mut state, 39, 5), Midi::HighRes(7, f) if close(f, (64 << 7) | 5)));
+        assert!(matches!(cc(&mut state, 39, 127), Midi::HighRes(7, f) if close(f, (64 << 7) | 127)));
+
+        // MSB only changes still move the value
+        assert!(matches!(cc(&mut state, 7, 65), Midi::HighRes(7, f) if close(f, 65 << 7)));
+
+        // Unpaired controllers decode as usual
+        assert!(matches!(cc(&mut state, 14, 126), Midi::Knob(0, f) if f == 1.0));
+    }
+
+    #[test]
+    fn nrpn_selection() {
+        let mut state = MidiState::default();
+        state.nrpn(true);
+
+        // Nothing selected yet
+        assert!(matches!(cc(&mut state, 6, 10), Midi::Unknown));
+
+        cc(&mut state, 99, 1);
+        cc(&mut state, 98, 2);
+        assert!(matches!(cc(&mut state, 6, 64), Midi::Nrpn(130, f) if close(f, 64 << 7)));
+        assert!(matches!(cc(&mut state, 38, 3), Midi::Nrpn(130, f) if close(f, (64 << 7) | 3)));
+
+        cc(&mut state, 101, 0);
+        cc(&mut state, 100, 0);
+        assert!(matches!(cc(&mut state, 6, 2), Midi::Rpn(0, f) if close(f, 2 << 7)));
+
+        // The null RPN closes the sequence
+        cc(&mut state, 101, 127);
+        cc(&mut state, 100, 127);
+        assert!(matches!(cc(&mut state, 6, 2), Midi::Unknown));
+    }
+
+    #[test]
+    fn nrpn_increment() {
+        let mut state = MidiState::default();
+        state.nrpn(true);
+
+        cc(&mut state, 99, 0);
+        cc(&mut state, 98, 1);
+        assert!(matches!(cc(&mut state, 97, 0), Midi::Nrpn(1, f) if close(f, 0)));
+        assert!(matches!(cc(&mut state, 96, 0), Midi::Nrpn(1, f) if close(f, 1)));
+        assert!(matches!(cc(&mut state, 96, 0), Midi::Nrpn(1, f) if close(f, 2)));
+
+        cc(&mut state, 6, 127);
+        cc(&mut state, 38, 127);
+        assert!(matches!(cc(&mut state, 96, 0), Midi::Nrpn(1, f) if close(f, 16383)));
+    }
+
+    #[test]
+    fn encoder_starts_where_it_is() {
+        let mut state = MidiState::default();
+
+        assert!(matches!(program(&mut state, 64), Midi::Unknown));
+        assert!(matches!(program(&mut state, 66), Midi::Encoder(2)));
+        assert!(matches!(program(&mut state, 65), Midi::Encoder(-1)));
+    }
+
+    #[test]
+    fn encoder_wraps_and_clamps() {
+        let mut state = MidiState::default();
+
+        program(&mut state, 126);
+        assert!(matches!(program(&mut state, 1), Midi::Encoder(3)));
+        assert!(matches!(program(&mut state, 0), Midi::Encoder(-1)));
+        assert!(matches!(program(&mut state, 0), Midi::Encoder(-1)));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::VecDeque;
+
+use crate::audio::Audio;
+use crate::time::MidiClock;
+
+pub mod message;
+pub mod smf;
+mod transport;
+mod jack;
+mod native;
+mod memory;
+mod feedback;
+mod learn;
+mod takeover;
+mod record;
+
+pub use message::{EncoderMode, Midi, MidiBank, MidiOut, MidiRaw};
+pub use transport::Transport;
+pub use self::jack::Jack;
+pub use native::{DeviceMatch, Native, NativeBuilder};
+pub use memory::{Memory, MemoryHandle, Script};
+pub use feedback::Feedback;
+pub use learn::{Control, Learn};
+pub use takeover::{Pickup, Takeover};
+pub use record::{MidiEvent, Player, Recorder, Recording, SmfPlayer};
+
+use message::MidiState;
+
+// A controller on some transport, parsed into Midi events
+pub struct Device {
+    pub clock: MidiClock,
+    transport: Box<dyn Transport>,
+    state: MidiState,
+    recorder: Option<Recorder>,
+    injected: VecDeque<(u64, MidiRaw)>,
+}
+
+impl Device {
+    pub fn new<T: Transport + 'static>(transport: T) -> Self {
+        Self {
+            clock: MidiClock::default(),
+            transport: Box::new(transport),
+            state: MidiState::default(),
+            recorder: None,
+            injected: VecDeque::new(),
+        }
+    }
+
+    // The MIDI ports on the JACK audio client
+    pub fn jack(audio: &Audio) -> Self {
+        Self::new(Jack::new(audio))
+    }
+
+    // Every hardware port through midir
+    pub fn native() -> Self {
+        Self::new(Native::default())
+    }
+
+    // An in-process transport, driven and observed through the returned handle
+    pub fn memory() -> (Self, MemoryHandle) {
+        let memory = Memory::default();
+        let handle = memory.handle();
+        (Self::new(memory), handle)
+    }
+
+    pub fn poll(&mut self) -> Vec<(MidiBank, Midi)> {
+        let mut raws = self.injected.drain(..).collect::<Vec<_>>();
+        raws.extend(self.transport.recv());
+
+        let mut messages = Vec::with_capacity(raws.len());
+        for (stamp, raw) in raws {
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record(stamp, &raw[..message::raw_len(&raw)]);
+            }
+
+            let (bank, msg) = self.state.process(raw);
+            self.clock.midi(stamp, msg);
+            messages.push((bank, msg));
+        }
+
+        messages
+    }
+
+    pub fn send(&mut self, msg: &MidiOut) {
+        self.transport.send(&msg.bytes());
+    }
+
+    pub fn send_raw(&mut self, bytes: &[u8]) {
+        self.transport.send(bytes);
+    }
+
+    // Feed a message through the next poll() as if it arrived from the transport
+    pub fn inject(&mut self, stamp: u64, bytes: &[u8]) {
+        self.injected.push_back((stamp, message::raw_from(bytes)));
+    }
+
+    // Capture every incoming message until stop_recording()
+    pub fn record(&mut self) {
+        self.recorder = Some(Recorder::default());
+    }
+
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        self.recorder.take().map(Recorder::finish)
+    }
+
+    pub fn relative(&mut self, cc: u8, mode: EncoderMode) {
+        self.state.relative(cc, mode);
+    }
+
+    pub fn high_res(&mut self, msb: u8) {
+        self.state.high_res(msb);
+    }
+
+    pub fn nrpn(&mut self, enable: bool) {
+        self.state.nrpn(enable);
+    }
+}
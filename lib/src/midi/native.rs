@@ -0,0 +1,314 @@
+use crossbeam_queue::SegQueue;
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::message::{self, MidiRaw};
+use super::transport::Transport;
+
+type MidiQueue = Arc<SegQueue<(u64, MidiRaw)>>;
+type MidiOutputSlot = Arc<Mutex<Option<MidiOutputConnection>>>;
+
+// Prefix of every client we open, so we never connect to our own ports
+const CLIENT: &str = "PHANTOMa_MIDI";
+
+// On JACK ports are named "client:port", on ALSA "client:port client:port"
+fn own(port: &str) -> bool {
+    port.starts_with(CLIENT)
+}
+
+#[derive(Debug, Clone)]
+pub enum DeviceMatch {
+    // Every hardware port, skipping the ALSA loopback
+    Any,
+    // Case insensitive substring of the port name
+    Name(String),
+    Regex(Regex),
+}
+
+impl DeviceMatch {
+    pub fn matches(&self, port: &str) -> bool {
+        if own(port) {
+            return false;
+        }
+
+        match self {
+            DeviceMatch::Any => !port.contains("Midi Through"),
+            DeviceMatch::Name(name) => port.to_lowercase().contains(&name.to_lowercase()),
+            DeviceMatch::Regex(re) => re.is_match(port),
+        }
+    }
+}
+
+// System MIDI ports through midir (ALSA, CoreMIDI, WinMM)
+pub struct Native {
+    queue: MidiQueue,
+    output: MidiOutputSlot,
+}
+
+impl Native {
+    pub fn builder() -> NativeBuilder {
+        NativeBuilder::default()
+    }
+
+    // Names of the currently available input ports, other than our own
+    pub fn ports() -> Vec<String> {
+        Ports::default().inputs()
+    }
+}
+
+impl Transport for Native {
+    fn recv(&mut self) -> Vec<(u64, MidiRaw)> {
+        let mut messages = Vec::with_capacity(self.queue.len());
+
+        while let Some(m) = self.queue.pop() {
+            messages.push(m);
+        }
+
+        messages
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        if let Some(output) = self.output.lock().unwrap().as_mut() {
+            if let Err(e) = output.send(bytes) {
+                log::warn!("Failed to send MIDI message {:?}: {}", bytes, e);
+            }
+        }
+    }
+}
+
+impl Default for Native {
+    fn default() -> Self {
+        NativeBuilder::default().any().build()
+    }
+}
+
+#[derive(Default)]
+pub struct NativeBuilder {
+    devices: Vec<DeviceMatch>,
+    output: Option<DeviceMatch>,
+}
+
+impl NativeBuilder {
+    // Connect to every hardware input port
+    pub fn any(mut self) -> Self {
+        self.devices.push(DeviceMatch::Any);
+        self
+    }
+
+    // Connect to every input port whose name contains `name`
+    pub fn device(mut self, name: &str) -> Self {
+        self.devices.push(DeviceMatch::Name(name.to_string()));
+        self
+    }
+
+    // Connect to every input port whose name matches `pattern`, or to every hardware port
+    // if it doesn't parse
+    pub fn regex(mut self, pattern: &str) -> Self {
+        match Regex::new(pattern) {
+            Ok(re) => self.devices.push(DeviceMatch::Regex(re)),
+            Err(e) => {
+                log::warn!("Invalid MIDI device pattern, connecting to any device: {}", e);
+                self.devices.push(DeviceMatch::Any);
+            }
+        }
+        self
+    }
+
+    // Send feedback to the first output port whose name contains `name`.
+    // Defaults to the first output matching an input device
+    pub fn output(mut self, name: &str) -> Self {
+        self.output = Some(DeviceMatch::Name(name.to_string()));
+        self
+    }
+
+    pub fn build(self) -> Native {
+        let queue = Arc::new(SegQueue::new());
+        let output = Arc::new(Mutex::new(None));
+
+        let sender = Arc::clone(&queue);
+        let outputs = Arc::clone(&output);
+        thread::spawn(move || watch(self.devices, self.output, sender, outputs));
+
+        Native { queue, output }
+    }
+}
+
+// How often to rescan ports for devices that were plugged or unplugged
+const RESCAN: Duration = Duration::from_secs(1);
+
+// Clients used only to list ports. Opening and closing clients reorders the JACK
+// graph the audio engine runs in, so these live as long as the watcher
+#[derive(Default)]
+struct Ports {
+    input: Option<MidiInput>,
+    output: Option<MidiOutput>,
+}
+
+impl Ports {
+    fn inputs(&mut self) -> Vec<String> {
+        if self.input.is_none() {
+            self.input = MidiInput::new(&format!("{}_LIST", CLIENT)).ok();
+        }
+
+        match &self.input {
+            Some(midi) => midi
+                .ports()
+                .iter()
+                .filter_map(|p| midi.port_name(p).ok())
+                .filter(|n| !own(n))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    fn outputs(&mut self) -> Vec<String> {
+        if self.output.is_none() {
+            self.output = MidiOutput::new(&format!("{}_LIST_OUT", CLIENT)).ok();
+        }
+
+        match &self.output {
+            Some(midi) => midi
+                .ports()
+                .iter()
+                .filter_map(|p| midi.port_name(p).ok())
+                .filter(|n| !own(n))
+                .collect(),
+            None => vec![],
+        }
+    }
+
+    // Start over after a failed connect, in case the listing clients went stale
+    fn reset(&mut self) {
+        self.input = None;
+        self.output = None;
+    }
+}
+
+fn watch(
+    devices: Vec<DeviceMatch>,
+    output: Option<DeviceMatch>,
+    queue: MidiQueue,
+    out: MidiOutputSlot,
+) {
+    let wanted = |name: &str| devices.iter().any(|d| d.matches(name));
+    let mut inputs: HashMap<String, MidiInputConnection<()>> = HashMap::new();
+    let mut output_name: Option<String> = None;
+    let mut ports = Ports::default();
+
+    if devices.is_empty() {
+        log::warn!("No MIDI devices requested");
+        return;
+    }
+
+    loop {
+        let names = ports.inputs();
+
+        // Drop connections to devices that went away
+        inputs.retain(|name, _| {
+            let present = names.contains(name);
+            if !present {
+                log::info!("MIDI device '{}' disconnected", name);
+            }
+            present
+        });
+
+        for name in names.iter().filter(|p| wanted(p)) {
+            if inputs.contains_key(name) {
+                continue;
+            }
+
+            match connect(name, Arc::clone(&queue)) {
+                Some(conn) => {
+                    log::info!("Using MIDI device '{}'", name);
+                    inputs.insert(name.clone(), conn);
+                }
+                None => {
+                    log::warn!("Failed to connect to MIDI device '{}'", name);
+                    ports.reset();
+                }
+            }
+        }
+
+        // Keep the feedback output attached to a live device
+        let names = ports.outputs();
+
+        if output_name.as_ref().map_or(false, |n| !names.contains(n)) {
+            log::info!("MIDI output '{}' disconnected", output_name.take().unwrap());
+            *out.lock().unwrap() = None;
+        }
+
+        if output_name.is_none() {
+            let found = names.iter().find(|n| match &output {
+                Some(d) => d.matches(n),
+                None => wanted(n),
+            });
+
+            if let Some(name) = found {
+                match connect_output(name) {
+                    Some(conn) => {
+                        log::info!("Using MIDI output '{}'", name);
+                        output_name = Some(name.clone());
+                        *out.lock().unwrap() = Some(conn);
+                    }
+                    None => {
+                        log::warn!("Failed to connect to MIDI output '{}'", name);
+                        ports.reset();
+                    }
+                }
+            }
+        }
+
+        thread::sleep(RESCAN);
+    }
+}
+
+fn connect(name: &str, queue: MidiQueue) -> Option<MidiInputConnection<()>> {
+    let mut midi = MidiInput::new(CLIENT).ok()?;
+    midi.ignore(Ignore::None);
+
+    let ports = midi.ports();
+    let port = ports
+        .iter()
+        .find(|p| midi.port_name(p).map_or(false, |n| n == name))?;
+
+    midi.connect(
+        port,
+        "midi_in",
+        move |stamp, raw, _| {
+            log::debug!("{:?}", raw);
+            queue.push((stamp, message::raw_from(raw)));
+        },
+        (),
+    )
+    .ok()
+}
+
+fn connect_output(name: &str) -> Option<MidiOutputConnection> {
+    let midi = MidiOutput::new(&format!("{}_OUT", CLIENT)).ok()?;
+
+    let ports = midi.ports();
+    let port = ports
+        .iter()
+        .find(|p| midi.port_name(p).map_or(false, |n| n == name))?;
+
+    midi.connect(port, "midi_out").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_regex_matches_any() {
+        let builder = NativeBuilder::default().regex("nanoKONTROL[0-9]").regex("(unclosed");
+
+        assert!(matches!(&builder.devices[..], [DeviceMatch::Regex(_), DeviceMatch::Any]));
+        assert!(builder.devices[0].matches("nanoKONTROL2:nanoKONTROL2 MIDI 1"));
+        assert!(!builder.devices[0].matches("Launchpad"));
+        assert!(builder.devices[1].matches("Launchpad"));
+    }
+}
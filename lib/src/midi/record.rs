@@ -0,0 +1,364 @@
+use serde::{Deserialize, Serialize};
+
+use std::path::Path;
+
+use super::Device;
+use super::smf::{self, Smf};
+use crate::time::BeatClock;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MidiEvent {
+    // Microseconds since the recording started
+    pub t: u64,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub events: Vec<MidiEvent>,
+}
+
+impl Recording {
+    // Native log, keeps everything including real-time messages
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let data = serde_json::to_vec(self)?;
+        std::fs::write(path, data)
+    }
+
+    pub fn save_smf<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let events = self
+            .events
+            .iter()
+            .map(|e| (e.t, e.bytes.clone()))
+            .collect::<Vec<_>>();
+
+        let file = std::fs::File::create(path)?;
+        smf::write(std::io::BufWriter::new(file), &events)
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.events.last().map_or(0, |e| e.t)
+    }
+}
+
+#[derive(Default)]
+pub struct Recorder {
+    start: Option<u64>,
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn record(&mut self, stamp: u64, bytes: &[u8]) {
+        let start = *self.start.get_or_insert(stamp);
+
+        self.recording.events.push(MidiEvent {
+            t: stamp.saturating_sub(start),
+            bytes: bytes.to_vec(),
+        });
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+// Replays a recording into the input queue, advanced by frame time so offline renders are deterministic
+pub struct Player {
+    pub looping: bool,
+    recording: Recording,
+    i: usize,
+    t: u64,
+    // Offsets stamps so the replayed stream is monotonic across loops
+    base: u64,
+}
+
+impl Player {
+    pub fn new(recording: Recording) -> Self {
+        Self {
+            looping: false,
+            recording,
+            i: 0,
+            t: 0,
+            base: 0,
+        }
+    }
+
+    pub fn rewind(&mut self) {
+        self.base += self.t;
+        self.i = 0;
+        self.t = 0;
+    }
+
+    pub fn done(&self) -> bool {
+        !self.looping && self.i >= self.recording.events.len()
+    }
+
+    pub fn update(&mut self, delta: f32, midi: &mut Device) {
+        self.t += (delta * 1_000_000.0) as u64;
+
+        loop {
+            while let Some(e) = self.recording.events.get(self.i) {
+                if e.t > self.t {
+                    return;
+                }
+
+                midi.inject(self.base + e.t, &e.bytes);
+                self.i += 1;
+            }
+
+            if !self.looping || self.recording.duration() == 0 {
+                return;
+            }
+
+            // Carry the overshoot into the next loop
+            let over = self.t - self.recording.duration();
+            self.rewind();
+            self.base -= over;
+            self.t = over;
+        }
+    }
+}
+
+// Plays a MIDI file into the input queue, following either wall time or a BeatClock
+pub struct SmfPlayer {
+    pub looping: bool,
+    // Beats and microseconds of each event
+    events: Vec<(f64, u64, Vec<u8>)>,
+    // Up to the end-of-track, which can be after the last event
+    len: (f64, u64),
+    i: usize,
+    beats: f64,
+    us: u64,
+    base: u64,
+}
+
+impl SmfPlayer {
+    pub fn new(smf: &Smf) -> Self {
+        Self {
+            looping: false,
+            events: smf.timed(),
+            len: smf.length(),
+            i: 0,
+            beats: 0.0,
+            us: 0,
+            base: 0,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        Ok(Self::new(&smf::parse(&data)?))
+    }
+
+    pub fn rewind(&mut self) {
+        self.base += self.us;
+        self.i = 0;
+        self.beats = 0.0;
+        self.us = 0;
+    }
+
+    pub fn done(&self) -> bool {
+        !self.looping && self.i >= self.events.len()
+    }
+
+    // Play at the file's own tempo map
+    pub fn update(&mut self, delta: f32, midi: &mut Device) {
+        self.us += (delta * 1_000_000.0) as u64;
+        self.emit(midi, |e, p| e.1 <= p.us);
+    }
+
+    // Play at the clock's tempo, with the beat phase locked to the clock
+    pub fn update_clock(&mut self, delta: f32, clock: &BeatClock, midi: &mut Device) {
+        self.beats += (delta * clock.bpm / 60.0) as f64;
+        self.us += (delta * 1_000_000.0) as u64;
+
+        // Clock multipliers above 1 span whole beats, so the beat phase is still known
+        if clock.mul >= 1.0 {
+            let phase = (clock.phase() * clock.mul).fract() as f64;
+            let d = (phase - self.beats.fract() + 0.5).rem_euclid(1.0) - 0.5;
+            self.beats = (self.beats + d).max(0.0);
+        }
+
+        self.emit(midi, |e, p| e.0 <= p.beats);
+    }
+
+    fn emit(&mut self, midi: &mut Device, due: impl Fn(&(f64, u64, Vec<u8>), &Self) -> bool) {
+        loop {
+            while let Some(e) = self.events.get(self.i) {
+                if !due(e, self) {
+                    return;
+                }
+
+                midi.inject(self.base + e.1, &e.2);
+                self.i += 1;
+            }
+
+            if !self.looping || (self.len.0 <= 0.0 && self.len.1 == 0) {
+                return;
+            }
+
+            // Hold off wrapping until the end of the track
+            if !due(&(self.len.0, self.len.1, Vec::new()), self) {
+                return;
+            }
+
+            // Wrap around, carrying the overshoot into the next loop
+            let over = (self.beats - self.len.0, self.us.saturating_sub(self.len.1));
+            self.rewind();
+            self.beats = over.0.max(0.0);
+            self.us = over.1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::smf::{EventKind, TrackEvent, DIVISION, END_OF_TRACK};
+    use crate::midi::Midi;
+
+    fn knob(tick: u64, v: u8) -> TrackEvent {
+        TrackEvent {
+            tick,
+            kind: EventKind::Midi(vec![0xB0, 14, v]),
+        }
+    }
+
+    fn knobs(midi: &mut Device) -> Vec<u8> {
+        midi.poll()
+            .into_iter()
+            .filter_map(|(_, m)| match m {
+                Midi::Knob(0, f) => Some((f * 126.0).round() as u8),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn recording(events: &[(u64, u8)]) -> Recording {
+        Recording {
+            events: events
+                .iter()
+                .map(|&(t, v)| MidiEvent {
+                    t,
+                    bytes: vec![0xB0, 14, v],
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn records_from_first_message() {
+        let (mut midi, handle) = Device::memory();
+
+        midi.record();
+        handle.feed(1_000_000, &[0xB0, 14, 63]);
+        handle.feed(1_250_000, &[0xB0, 14, 126]);
+        midi.poll();
+        handle.feed(1_500_000, &[0xF8]);
+        midi.poll();
+
+        let recording = midi.stop_recording().unwrap();
+        let times = recording.events.iter().map(|e| e.t).collect::<Vec<_>>();
+        assert_eq!(times, vec![0, 250_000, 500_000]);
+        assert_eq!(recording.events[1].bytes, vec![0xB0, 14, 126]);
+        assert_eq!(recording.events[2].bytes, vec![0xF8]);
+        assert_eq!(recording.duration(), 500_000);
+        assert!(midi.stop_recording().is_none());
+    }
+
+    #[test]
+    fn replays_on_time() {
+        let (mut midi, _) = Device::memory();
+        let mut player = Player::new(recording(&[(0, 0), (500_000, 63), (1_000_000, 126)]));
+
+        player.update(0.25, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![0]);
+        player.update(0.25, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![63]);
+        player.update(0.4, &mut midi);
+        assert!(knobs(&mut midi).is_empty());
+        assert!(!player.done());
+
+        player.update(0.1, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![126]);
+        assert!(player.done());
+
+        player.update(1.0, &mut midi);
+        assert!(knobs(&mut midi).is_empty());
+    }
+
+    #[test]
+    fn loops_with_rising_stamps() {
+        let (mut midi, _) = Device::memory();
+        let mut player = Player::new(recording(&[(0, 0), (250_000, 63), (500_000, 126)]));
+        player.looping = true;
+
+        midi.record();
+        player.update(0.375, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![0, 63]);
+
+        // The overshoot carries into the next loop
+        player.update(0.5, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![126, 0, 63]);
+        assert!(!player.done());
+
+        let replayed = midi.stop_recording().unwrap();
+        let times = replayed.events.iter().map(|e| e.t).collect::<Vec<_>>();
+        assert_eq!(times, vec![0, 250_000, 500_000, 500_000, 750_000]);
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let path = std::env::temp_dir().join(format!("recording-{}.json", std::process::id()));
+        let recording = recording(&[(0, 0), (250_000, 63)]);
+
+        recording.save(&path).unwrap();
+        let loaded = Recording::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.events.len(), 2);
+        for (a, b) in recording.events.iter().zip(loaded.events.iter()) {
+            assert_eq!((a.t, &a.bytes), (b.t, &b.bytes));
+        }
+    }
+
+    #[test]
+    fn smf_loops_at_end_of_track() {
+        // A one bar loop whose last note is on the second eighth
+        let smf = Smf {
+            format: 0,
+            division: DIVISION,
+            tracks: vec![vec![
+                knob(0, 0),
+                knob(DIVISION as u64 / 2, 63),
+                TrackEvent {
+                    tick: 4 * DIVISION as u64,
+                    kind: EventKind::Meta(END_OF_TRACK, Vec::new()),
+                },
+            ]],
+        };
+
+        let (mut midi, _) = Device::memory();
+        let mut player = SmfPlayer::new(&smf);
+        player.looping = true;
+
+        // A beat is half a second at the default tempo
+        player.update(0.5, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![0, 63]);
+
+        player.update(0.5, &mut midi);
+        player.update(0.5, &mut midi);
+        assert!(knobs(&mut midi).is_empty());
+
+        // Back to the start after the full bar, not after the last note
+        player.update(0.5, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![0]);
+        player.update(0.5, &mut midi);
+        assert_eq!(knobs(&mut midi), vec![63]);
+    }
+}
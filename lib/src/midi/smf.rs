@@ -0,0 +1,518 @@
+// Standard MIDI File support
+
+use std::io::{self, Write};
+
+// Ticks per quarter note, and the default tempo (also used when writing)
+pub const DIVISION: u16 = 480;
+pub const TEMPO: u32 = 500_000;
+
+// Meta event closing each track, kept so a loop can end after its last note
+pub const END_OF_TRACK: u8 = 0x2F;
+// Largest value a variable length quantity can hold in a file
+const MAX_VLQ: u64 = 0x0FFF_FFFF;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    Midi(Vec<u8>),
+    // Microseconds per quarter note
+    Tempo(u32),
+    Meta(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackEvent {
+    pub tick: u64,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Smf {
+    pub format: u16,
+    // Ticks per quarter note
+    pub division: u16,
+    pub tracks: Vec<Vec<TrackEvent>>,
+}
+
+impl Smf {
+    // All tracks merged in time order, keeping file order for simultaneous events
+    pub fn merged(&self) -> Vec<&TrackEvent> {
+        let mut events = self.tracks.iter().flatten().collect::<Vec<_>>();
+        events.sort_by_key(|e| e.tick);
+        events
+    }
+
+    // Channel and sysex messages with their time in beats and microseconds, following the tempo map
+    pub fn timed(&self) -> Vec<(f64, u64, Vec<u8>)> {
+        let division = self.division as f64;
+        let mut tempo = TEMPO as f64;
+        let (mut last, mut us) = (0, 0.0);
+
+        let mut events = Vec::new();
+        for e in self.merged() {
+            us += (e.tick - last) as f64 / division * tempo;
+            last = e.tick;
+
+            match &e.kind {
+                EventKind::Tempo(t) => tempo = *t as f64,
+                EventKind::Midi(bytes) => {
+                    events.push((e.tick as f64 / division, us as u64, bytes.clone()))
+                }
+                EventKind::Meta(..) => {}
+            }
+        }
+
+        events
+    }
+
+    // Tick where the longest track ends, at its end-of-track event
+    pub fn end(&self) -> u64 {
+        self.tracks.iter().flatten().map(|e| e.tick).max().unwrap_or(0)
+    }
+
+    // Length in beats and microseconds up to end(), following the tempo map
+    pub fn length(&self) -> (f64, u64) {
+        let division = self.division as f64;
+        let mut tempo = TEMPO as f64;
+        let (mut last, mut us) = (0, 0.0);
+
+        for e in self.merged() {
+            us += (e.tick - last) as f64 / division * tempo;
+            last = e.tick;
+
+            if let EventKind::Tempo(t) = e.kind {
+                tempo = t as f64;
+            }
+        }
+
+        (last as f64 / division, us as u64)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    i: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if self.i + n > self.data.len() {
+            return Err(invalid("Unexpected end of MIDI file"));
+        }
+
+        let bytes = &self.data[self.i..self.i + n];
+        self.i += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn vlq(&mut self) -> io::Result<u32> {
+        let mut v = 0u32;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            v = (v << 7) | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+
+        Err(invalid("Variable length quantity too long"))
+    }
+
+    fn done(&self) -> bool {
+        self.i >= self.data.len()
+    }
+}
+
+pub fn parse(data: &[u8]) -> io::Result<Smf> {
+    let mut r = Reader { data, i: 0 };
+
+    if r.take(4)? != b"MThd" {
+        return Err(invalid("Not a MIDI file"));
+    }
+    let len = r.u32()? as usize;
+    let format = r.u16()?;
+    let ntracks = r.u16()?;
+    let division = r.u16()?;
+    r.take(len.saturating_sub(6))?;
+
+    if division & 0x8000 != 0 {
+        return Err(invalid("SMPTE time division is not supported"));
+    }
+
+    let mut tracks = Vec::with_capacity(ntracks as usize);
+    while tracks.len() < ntracks as usize && !r.done() {
+        let id = r.take(4)?;
+        let len = r.u32()? as usize;
+        let chunk = r.take(len)?;
+
+        // Skip unknown chunks as the spec asks
+        if id == b"MTrk" {
+            tracks.push(parse_track(chunk)?);
+        }
+    }
+
+    Ok(Smf {
+        format,
+        division,
+        tracks,
+    })
+}
+
+fn parse_track(data: &[u8]) -> io::Result<Vec<TrackEvent>> {
+    let mut r = Reader { data, i: 0 };
+    let mut events = Vec::new();
+    let mut tick = 0u64;
+    let mut running = None;
+
+    while !r.done() {
+        tick += r.vlq()? as u64;
+
+        let mut status = r.u8()?;
+        let kind = match status {
+            0xFF => {
+                let ty = r.u8()?;
+                let len = r.vlq()? as usize;
+                let data = r.take(len)?;
+
+                match ty {
+                    END_OF_TRACK => {
+                        events.push(TrackEvent {
+                            tick,
+                            kind: EventKind::Meta(END_OF_TRACK, Vec::new()),
+                        });
+                        break;
+                    }
+                    0x51 if len == 3 => {
+                        EventKind::Tempo(u32::from_be_bytes([0, data[0], data[1], data[2]]))
+                    }
+                    _ => EventKind::Meta(ty, data.to_vec()),
+                }
+            }
+            0xF0 | 0xF7 => {
+                let len = r.vlq()? as usize;
+                let data = r.take(len)?;
+
+                // 0xF7 escapes arbitrary bytes, 0xF0 is a sysex missing its leading byte
+                let mut bytes = Vec::with_capacity(len + 1);
+                if status == 0xF0 {
+                    bytes.push(0xF0);
+                }
+                bytes.extend_from_slice(data);
+                EventKind::Midi(bytes)
+            }
+            _ => {
+                // Running status reuses the last status byte, so this is already data
+                let first = if status < 0x80 {
+                    let first = status;
+                    status = running.ok_or_else(|| invalid("Running status without a status byte"))?;
+                    Some(first)
+                } else {
+                    running = Some(status);
+                    None
+                };
+
+                let n = match status {
+                    0xC0..=0xDF => 1,
+                    _ => 2,
+                };
+
+                let mut bytes = vec![status];
+                match first {
+                    Some(b) => {
+                        bytes.push(b);
+                        bytes.extend_from_slice(r.take(n - 1)?);
+                    }
+                    None => bytes.extend_from_slice(r.take(n)?),
+                }
+                EventKind::Midi(bytes)
+            }
+        };
+
+        events.push(TrackEvent { tick, kind });
+    }
+
+    Ok(events)
+}
+
+fn write_vlq(out: &mut Vec<u8>, mut v: u64) -> io::Result<()> {
+    if v > MAX_VLQ {
+        return Err(invalid("Value too large for a MIDI file"));
+    }
+
+    let mut buf = [0u8; 4];
+    let mut n = 0;
+
+    loop {
+        buf[n] = (v & 0x7F) as u8;
+        n += 1;
+        v >>= 7;
+        if v == 0 {
+            break;
+        }
+    }
+
+    for i in (0..n).rev() {
+        let cont = if i > 0 { 0x80 } else { 0 };
+        out.push(buf[i] | cont);
+    }
+
+    Ok(())
+}
+
+fn us_ticks(us: u64) -> u64 {
+    us * DIVISION as u64 / TEMPO as u64
+}
+
+fn write_track(out: &mut Vec<u8>, events: &[TrackEvent]) -> io::Result<()> {
+    let mut last = 0;
+    let mut running = None;
+
+    for e in events {
+        write_vlq(out, e.tick.saturating_sub(last))?;
+        last = e.tick.max(last);
+
+        match &e.kind {
+            EventKind::Midi(bytes) => match bytes.first().copied() {
+                // Channel messages, leaving out the status byte when it repeats
+                Some(status @ 0x80..=0xEF) => {
+                    if running != Some(status) {
+                        out.push(status);
+                        running = Some(status);
+                    }
+                    out.extend_from_slice(&bytes[1..]);
+                }
+                Some(0xF0) => {
+                    out.push(0xF0);
+                    write_vlq(out, bytes.len() as u64 - 1)?;
+                    out.extend_from_slice(&bytes[1..]);
+                    running = None;
+                }
+                // Anything else goes out escaped, as parse() reads it back
+                _ => {
+                    out.push(0xF7);
+                    write_vlq(out, bytes.len() as u64)?;
+                    out.extend_from_slice(bytes);
+                    running = None;
+                }
+            },
+            EventKind::Tempo(tempo) => {
+                out.extend_from_slice(&[0xFF, 0x51, 0x03]);
+                out.extend_from_slice(&tempo.to_be_bytes()[1..]);
+                running = None;
+            }
+            EventKind::Meta(ty, data) => {
+                out.extend_from_slice(&[0xFF, *ty]);
+                write_vlq(out, data.len() as u64)?;
+                out.extend_from_slice(data);
+                running = None;
+
+                // Anything after the end of the track would be lost to parse()
+                if *ty == END_OF_TRACK {
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // End of track, straight after the last event
+    out.extend_from_slice(&[0x00, 0xFF, END_OF_TRACK, 0x00]);
+    Ok(())
+}
+
+impl Smf {
+    // Events in each track must be in time order
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(b"MThd")?;
+        w.write_all(&6u32.to_be_bytes())?;
+        w.write_all(&self.format.to_be_bytes())?;
+        w.write_all(&(self.tracks.len() as u16).to_be_bytes())?;
+        w.write_all(&self.division.to_be_bytes())?;
+
+        for events in &self.tracks {
+            let mut track = Vec::new();
+            write_track(&mut track, events)?;
+
+            w.write_all(b"MTrk")?;
+            w.write_all(&(track.len() as u32).to_be_bytes())?;
+            w.write_all(&track)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Write timestamped (microseconds) raw messages as a format 0 file at 120 BPM.
+// Only channel messages and sysex have a representation in a file, system common
+// and real-time messages like clock are skipped
+pub fn write<W: Write>(w: W, events: &[(u64, Vec<u8>)]) -> io::Result<()> {
+    let mut track = vec![TrackEvent {
+        tick: 0,
+        kind: EventKind::Tempo(TEMPO),
+    }];
+
+    let mut last = 0;
+    for (stamp, bytes) in events {
+        match bytes.first().copied() {
+            Some(0x80..=0xEF) | Some(0xF0) => {}
+            _ => continue,
+        }
+
+        // Keep the track in order even if stamps jitter backwards
+        last = us_ticks(*stamp).max(last);
+        track.push(TrackEvent {
+            tick: last,
+            kind: EventKind::Midi(bytes.clone()),
+        });
+    }
+
+    Smf {
+        format: 0,
+        division: DIVISION,
+        tracks: vec![track],
+    }
+    .write(w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi(tick: u64, bytes: &[u8]) -> TrackEvent {
+        TrackEvent {
+            tick,
+            kind: EventKind::Midi(bytes.to_vec()),
+        }
+    }
+
+    fn tempo(tick: u64, tempo: u32) -> TrackEvent {
+        TrackEvent {
+            tick,
+            kind: EventKind::Tempo(tempo),
+        }
+    }
+
+    fn end(tick: u64) -> TrackEvent {
+        TrackEvent {
+            tick,
+            kind: EventKind::Meta(END_OF_TRACK, Vec::new()),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let smf = Smf {
+            format: 1,
+            division: DIVISION,
+            tracks: vec![
+                // Tempo map, doubling speed after two beats
+                vec![tempo(0, TEMPO), tempo(960, TEMPO / 2), end(960)],
+                vec![
+                    midi(0, &[0x90, 60, 100]),
+                    midi(0, &[0x90, 64, 100]),
+                    midi(480, &[0x80, 60, 0]),
+                    midi(480, &[0x80, 64, 0]),
+                    midi(960, &[0xC0, 5]),
+                    midi(1440, &[0x90, 67, 100]),
+                    midi(1440, &[0xF0, 0x7D, 0x01, 0xF7]),
+                    // A bar and a half, past the last note
+                    end(2160),
+                ],
+            ],
+        };
+
+        let mut data = Vec::new();
+        smf.write(&mut data).unwrap();
+        assert_eq!(parse(&data).unwrap(), smf);
+
+        // Repeated status bytes were left out of the note track
+        let i = data.windows(4).rposition(|w| w == b"MTrk").unwrap();
+        let track = &data[i + 8..];
+        assert_eq!(track.iter().filter(|&&b| b == 0x90).count(), 2);
+        assert_eq!(track.iter().filter(|&&b| b == 0x80).count(), 1);
+
+        // Times follow the tempo change
+        let us = smf.timed().iter().map(|e| e.1).collect::<Vec<_>>();
+        assert_eq!(us, vec![0, 0, 500_000, 500_000, 1_000_000, 1_250_000, 1_250_000]);
+        assert_eq!(smf.end(), 2160);
+        assert_eq!(smf.length(), (4.5, 1_625_000));
+    }
+
+    #[test]
+    fn running_status() {
+        #[rustfmt::skip]
+        let data = [
+            b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 1, 0x01, 0xE0,
+            b'M', b'T', b'r', b'k', 0, 0, 0, 11,
+            0x00, 0x90, 60, 100,
+            0x10, 62, 100,
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+
+        let smf = parse(&data).unwrap();
+        assert_eq!(
+            smf.tracks[0],
+            vec![midi(0, &[0x90, 60, 100]), midi(16, &[0x90, 62, 100]), end(16)]
+        );
+    }
+
+    #[test]
+    fn write_skips_system_messages() {
+        let events = vec![
+            (0, vec![0x90, 60, 100]),
+            (1_000, vec![0xF8]),
+            (2_000, vec![0xF2, 0x10, 0x00]),
+            (3_000, vec![0xF6]),
+            (500_000, vec![0x80, 60, 0]),
+        ];
+
+        let mut data = Vec::new();
+        write(&mut data, &events).unwrap();
+
+        let smf = parse(&data).unwrap();
+        assert_eq!(
+            smf.tracks[0],
+            vec![tempo(0, TEMPO), midi(0, &[0x90, 60, 100]), midi(480, &[0x80, 60, 0]), end(480)]
+        );
+    }
+
+    #[test]
+    fn vlq_limits() {
+        let mut out = Vec::new();
+        write_vlq(&mut out, 0).unwrap();
+        write_vlq(&mut out, 0x7F).unwrap();
+        write_vlq(&mut out, 0x80).unwrap();
+        write_vlq(&mut out, MAX_VLQ).unwrap();
+        assert_eq!(out, vec![0x00, 0x7F, 0x81, 0x00, 0xFF, 0xFF, 0xFF, 0x7F]);
+
+        assert!(write_vlq(&mut out, MAX_VLQ + 1).is_err());
+        assert!(write_vlq(&mut out, u32::MAX as u64).is_err());
+    }
+
+    #[test]
+    fn write_rejects_long_gaps() {
+        let smf = Smf {
+            format: 0,
+            division: DIVISION,
+            tracks: vec![vec![midi(0, &[0x90, 60, 100]), midi(MAX_VLQ + 1, &[0x80, 60, 0])]],
+        };
+
+        assert!(smf.write(&mut Vec::new()).is_err());
+    }
+}
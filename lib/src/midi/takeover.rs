@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use super::learn::Control;
+use super::message::MidiBank;
+
+// What an absolute control does when its position doesn't match the value it drives
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Pickup {
+    // Apply every move immediately, letting the value jump
+    Jump,
+    // Ignore the control until it passes through the current value
+    Pickup,
+    // Scale movement so the control and value meet at the end of travel
+    Scale,
+}
+
+impl Default for Pickup {
+    fn default() -> Self {
+        Pickup::Pickup
+    }
+}
+
+// Soft takeover for absolute controls after bank switches or preset loads
+pub struct Takeover {
+    pub default: Pickup,
+    modes: HashMap<Control, Pickup>,
+    physical: HashMap<Control, f32>,
+    written: HashMap<(MidiBank, Control), (f32, bool)>,
+}
+
+impl Takeover {
+    // How close the control has to be to the value to pick it up
+    const THRESHOLD: f32 = 1.0 / 64.0;
+    // Slack for round trips through a parameter's curve
+    const EPSILON: f32 = 1e-3;
+
+    pub fn mode(&mut self, control: Control, pickup: Pickup) {
+        self.modes.insert(control, pickup);
+    }
+
+    // Forget which controls are in sync, e.g. after loading a preset
+    pub fn release(&mut self) {
+        self.written.clear();
+    }
+
+    // Filter a move of `control` to `f` against the normalized value `current` it drives.
+    // Returns the normalized value to apply, or None while waiting for pickup
+    pub fn process(&mut self, bank: MidiBank, control: Control, f: f32, current: f32) -> Option<f32> {
+        let last = self.physical.insert(control, f);
+
+        if control.relative() {
+            return Some(f);
+        }
+
+        let key = (bank, control);
+        let mode = self.modes.get(&control).copied().unwrap_or(self.default);
+
+        // Still in control if nothing else touched the value since we last wrote it
+        let caught = match self.written.get(&key) {
+            Some(&(v, caught)) => caught && (v - current).abs() < Self::EPSILON,
+            None => false,
+        };
+
+        let v = if caught {
+            Some(f)
+        } else {
+            match mode {
+                Pickup::Jump => Some(f),
+                Pickup::Pickup => {
+                    let crossed = last.map_or(false, |l| (l - current) * (f - current) <= 0.0);
+                    if crossed || (f - current).abs() < Self::THRESHOLD {
+                        Some(f)
+                    } else {
+                        None
+                    }
+                }
+                Pickup::Scale => match last {
+                    // Need a previous position to know which way the control is moving
+                    Some(l) if (f - l).abs() > 0.0 => {
+                        let v = if f > l {
+                            current + (f - l) * (1.0 - current) / (1.0 - l).max(f32::EPSILON)
+                        } else {
+                            current - (l - f) * current / l.max(f32::EPSILON)
+                        };
+                        Some(v.max(0.0).min(1.0))
+                    }
+                    _ => None,
+                },
+            }
+        };
+
+        if let Some(v) = v {
+            self.written.insert(key, (v, (v - f).abs() < Self::THRESHOLD));
+        }
+
+        v
+    }
+}
+
+impl Default for Takeover {
+    fn default() -> Self {
+        Self {
+            default: Pickup::default(),
+            modes: HashMap::new(),
+            physical: HashMap::new(),
+            written: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SLIDER: Control = Control::Slider(0);
+
+    fn takeover(pickup: Pickup) -> Takeover {
+        Takeover {
+            default: pickup,
+            ..Takeover::default()
+        }
+    }
+
+    #[test]
+    fn jump() {
+        let mut t = takeover(Pickup::Jump);
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.1, 0.5), Some(0.1));
+    }
+
+    #[test]
+    fn pickup_waits_for_the_value() {
+        let mut t = takeover(Pickup::Pickup);
+
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.1, 0.5), None);
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.3, 0.5), None);
+        // Passing through the value picks it up, even between messages
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.6, 0.5), Some(0.6));
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.7, 0.6), Some(0.7));
+
+        // Close enough counts as caught straight away
+        let mut t = takeover(Pickup::Pickup);
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.505, 0.5), Some(0.505));
+    }
+
+    #[test]
+    fn pickup_after_release() {
+        let mut t = takeover(Pickup::Pickup);
+        t.process(MidiBank::B0, SLIDER, 0.5, 0.5);
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.9, 0.5), Some(0.9));
+
+        // A preset moved the value, the slider has to find it again
+        t.release();
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.8, 0.2), None);
+    }
+
+    #[test]
+    fn scale_meets_at_the_end() {
+        let mut t = takeover(Pickup::Scale);
+
+        // Direction unknown from the first move
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.2, 0.5), None);
+
+        let v = t.process(MidiBank::B0, SLIDER, 0.4, 0.5).unwrap();
+        assert!((v - 0.625).abs() < 1e-6);
+        let v = t.process(MidiBank::B0, SLIDER, 1.0, v).unwrap();
+        assert!((v - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn modes_per_control() {
+        let mut t = takeover(Pickup::Pickup);
+        t.mode(Control::Knob(0), Pickup::Jump);
+
+        assert_eq!(t.process(MidiBank::B0, Control::Knob(0), 0.1, 0.5), Some(0.1));
+        assert_eq!(t.process(MidiBank::B0, SLIDER, 0.1, 0.5), None);
+        // Relative controls aren't positions
+        assert_eq!(t.process(MidiBank::B0, Control::Encoder, -1.0, 0.5), Some(-1.0));
+    }
+}
@@ -0,0 +1,9 @@
+use super::message::MidiRaw;
+
+// Moves raw MIDI to and from somewhere. Parsing happens in Device so every backend behaves the same
+pub trait Transport: Send {
+    // Messages received since the last call, timestamped in microseconds
+    fn recv(&mut self) -> Vec<(u64, MidiRaw)>;
+
+    fn send(&mut self, bytes: &[u8]);
+}
@@ -0,0 +1,345 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::audio::Audio;
+use crate::envelope::Envelope;
+use crate::lfo::Lfo;
+use crate::midi::{Control, Midi, MidiBank};
+use crate::curve::Ease;
+use crate::param::Params;
+use crate::time::BeatClock;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Source {
+    Lfo(String),
+    Env(String),
+    // RMS of a frequency range in Hz
+    Band(f32, f32),
+    Rms,
+    Peak,
+    // Last value of a MIDI control
+    Midi(MidiBank, Control),
+}
+
+// What fires an envelope source
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    // Every BeatClock beat
+    Beat,
+    // Every BeatDetect hit
+    Hit,
+    // Only through Matrix::trigger
+    Manual,
+}
+
+// Adds `offset + depth * curve(source)` to a parameter's normalized position
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub source: Source,
+    pub dest: String,
+    pub depth: f32,
+    pub offset: f32,
+    pub curve: Ease,
+    pub enabled: bool,
+}
+
+impl Route {
+    pub fn new(source: Source, dest: &str, depth: f32) -> Self {
+        Self {
+            source,
+            dest: dest.to_string(),
+            depth,
+            offset: 0.0,
+            curve: Ease::Linear,
+            enabled: true,
+        }
+    }
+
+    pub fn offset(mut self, offset: f32) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn curve(mut self, curve: Ease) -> Self {
+        self.curve = curve;
+        self
+    }
+}
+
+// Everything the sources read from this frame
+pub struct Inputs<'a> {
+    pub clock: &'a BeatClock,
+    // What clock.update() returned
+    pub beat: bool,
+    // What BeatDetect::update() returned
+    pub hit: bool,
+    pub audio: Option<&'a Audio>,
+}
+
+// Routes any number of sources onto named parameters. Each parameter keeps the value
+// its controls set, and modulation is summed on top of it in normalized space
+pub struct Matrix {
+    // Audio levels are raw FFT and sample magnitudes, scaled by this into [0.0, 1.0]
+    pub gain: f32,
+    routes: Vec<Route>,
+    lfos: HashMap<String, Lfo>,
+    envs: HashMap<String, (Box<dyn Envelope>, Trigger)>,
+    controls: HashMap<(MidiBank, Control), f32>,
+    // Summed modulation per destination, from the last update
+    amounts: HashMap<String, f32>,
+}
+
+impl Default for Matrix {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            routes: Vec::new(),
+            lfos: HashMap::new(),
+            envs: HashMap::new(),
+            controls: HashMap::new(),
+            amounts: HashMap::new(),
+        }
+    }
+}
+
+impl Matrix {
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let routes: Vec<Route> = serde_json::from_slice(&data)?;
+
+        Ok(Self {
+            routes,
+            ..Self::default()
+        })
+    }
+
+    // Only the routes are saved, sources are set up in code
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.routes)?;
+        std::fs::write(path, data)
+    }
+
+    pub fn gain(mut self, gain: f32) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn lfo(mut self, name: &str, lfo: Lfo) -> Self {
+        self.lfos.insert(name.to_string(), lfo);
+        self
+    }
+
+    pub fn env<E: Envelope + 'static>(mut self, name: &str, env: E, trigger: Trigger) -> Self {
+        self.envs.insert(name.to_string(), (Box::new(env), trigger));
+        self
+    }
+
+    pub fn with(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    // Add a route, returning its index
+    pub fn route(&mut self, route: Route) -> usize {
+        self.routes.push(route);
+        self.routes.len() - 1
+    }
+
+    pub fn remove(&mut self, i: usize) -> Option<Route> {
+        if i < self.routes.len() {
+            Some(self.routes.remove(i))
+        } else {
+            None
+        }
+    }
+
+    // Drop every route into a parameter
+    pub fn clear(&mut self, dest: &str) {
+        self.routes.retain(|r| r.dest != dest);
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+
+    pub fn routes_mut(&mut self) -> &mut [Route] {
+        &mut self.routes
+    }
+
+    pub fn get_lfo_mut(&mut self, name: &str) -> Option<&mut Lfo> {
+        self.lfos.get_mut(name)
+    }
+
+    pub fn trigger(&mut self, name: &str) {
+        match self.envs.get_mut(name) {
+            Some((env, _)) => env.trigger(),
+            None => log::warn!("Unknown envelope '{}'", name),
+        }
+    }
+
+    // Remember MIDI control values for Source::Midi
+    pub fn midi(&mut self, bank: MidiBank, msg: Midi) {
+        if let Some((control, f)) = Control::of(msg) {
+            if !control.relative() {
+                self.controls.insert((bank, control), f);
+            }
+        }
+    }
+
+    pub fn update(&mut self, delta: f32, inputs: &Inputs) {
+        for lfo in self.lfos.values_mut() {
+            lfo.update(delta, inputs.clock, inputs.beat);
+        }
+
+        for (env, trigger) in self.envs.values_mut() {
+            match trigger {
+                Trigger::Beat if inputs.beat => env.trigger(),
+                Trigger::Hit if inputs.hit => env.trigger(),
+                _ => {}
+            }
+            env.update(delta);
+        }
+
+        let mut amounts = HashMap::new();
+        for route in self.routes.iter().filter(|r| r.enabled) {
+            let s = match self.source(&route.source, inputs.audio) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            *amounts.entry(route.dest.clone()).or_insert(0.0) += route.offset + route.depth * route.curve.apply(s);
+        }
+        self.amounts = amounts;
+    }
+
+    // A source's current value, clamped to [0.0, 1.0] so curves apply to it
+    pub fn source(&self, source: &Source, audio: Option<&Audio>) -> Option<f32> {
+        let v = match source {
+            Source::Lfo(name) => self.lfos.get(name).map(Lfo::unipolar),
+            Source::Env(name) => self.envs.get(name).map(|(env, _)| env.v()),
+            Source::Band(f0, f1) => audio.map(|a| a.rms_range(*f0, *f1) * self.gain),
+            Source::Rms => audio.map(|a| a.rms() * self.gain),
+            Source::Peak => audio.map(|a| a.peak() * self.gain),
+            Source::Midi(bank, control) => Some(self.controls.get(&(*bank, *control)).copied().unwrap_or(0.0)),
+        };
+
+        v.map(|v| v.max(0.0).min(1.0))
+    }
+
+    // Summed modulation of a parameter's normalized position
+    pub fn amount(&self, name: &str) -> f32 {
+        self.amounts.get(name).copied().unwrap_or(0.0)
+    }
+
+    // A parameter's value with modulation applied, clamped to its range
+    pub fn v(&self, params: &Params, name: &str) -> f32 {
+        let p = match params.get(name) {
+            Some(p) => p,
+            None => return 0.0,
+        };
+
+        match self.amounts.get(name) {
+            Some(amount) if p.max != p.min => {
+                // Around where the parameter is now, not where it's slewing to
+                let f = p.curve.invert((p.v - p.min) / (p.max - p.min));
+                let f = (f + amount).max(0.0).min(1.0);
+                p.min + p.curve.apply(f) * (p.max - p.min)
+            }
+            _ => p.v,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::param::Param;
+
+    fn knob(i: u8) -> Source {
+        Source::Midi(MidiBank::B0, Control::Knob(i))
+    }
+
+    fn update(matrix: &mut Matrix) {
+        let clock = BeatClock::new(120.0);
+        let inputs = Inputs {
+            clock: &clock,
+            beat: false,
+            hit: false,
+            audio: None,
+        };
+        matrix.update(0.0, &inputs);
+    }
+
+    #[test]
+    fn sums_routes() {
+        let mut matrix = Matrix::default()
+            .with(Route::new(knob(0), "x", 0.5))
+            .with(Route::new(knob(1), "x", 0.25).offset(0.1))
+            .with(Route::new(knob(2), "y", 1.0));
+        let off = matrix.route(Route::new(knob(0), "x", 1.0));
+        matrix.routes_mut()[off].enabled = false;
+
+        matrix.midi(MidiBank::B0, Midi::Knob(0, 1.0));
+        matrix.midi(MidiBank::B0, Midi::Knob(1, 0.4));
+        update(&mut matrix);
+
+        assert!((matrix.amount("x") - 0.7).abs() < 1e-6);
+        assert_eq!(matrix.amount("y"), 0.0);
+        assert_eq!(matrix.amount("z"), 0.0);
+    }
+
+    #[test]
+    fn sources_are_clamped() {
+        let mut matrix = Matrix::default();
+        matrix.midi(MidiBank::B0, Midi::Knob(0, 1.0));
+        assert_eq!(matrix.source(&knob(0), None), Some(1.0));
+        assert_eq!(matrix.source(&Source::Rms, None), None);
+        assert_eq!(matrix.source(&Source::Lfo("none".to_string()), None), None);
+
+        // Relative controls aren't levels
+        matrix.midi(MidiBank::B0, Midi::Encoder(5));
+        assert_eq!(matrix.source(&Source::Midi(MidiBank::B0, Control::Encoder), None), Some(0.0));
+    }
+
+    #[test]
+    fn v_clamps_to_range() {
+        let params = Params::default()
+            .with("x", Param::new(5.0, 0.0, 10.0))
+            .with("y", Param::new(5.0, 0.0, 10.0));
+        let mut matrix = Matrix::default()
+            .with(Route::new(knob(0), "x", 1.0))
+            .with(Route::new(knob(0), "y", -1.0));
+
+        matrix.midi(MidiBank::B0, Midi::Knob(0, 0.2));
+        update(&mut matrix);
+        assert!((matrix.v(&params, "x") - 7.0).abs() < 1e-4);
+        assert!((matrix.v(&params, "y") - 3.0).abs() < 1e-4);
+
+        matrix.midi(MidiBank::B0, Midi::Knob(0, 1.0));
+        update(&mut matrix);
+        assert_eq!(matrix.v(&params, "x"), 10.0);
+        assert_eq!(matrix.v(&params, "y"), 0.0);
+        assert_eq!(matrix.v(&params, "unknown"), 0.0);
+    }
+
+    #[test]
+    fn save_load() {
+        let path = std::env::temp_dir().join(format!("matrix-{}.json", std::process::id()));
+        let matrix = Matrix::default()
+            .with(Route::new(Source::Lfo("slow".to_string()), "fx/edge", 0.5).curve(Ease::Exp))
+            .with(Route::new(Source::Band(20.0, 200.0), "fx/glitch", 1.0).offset(-0.2));
+        matrix.save(&path).unwrap();
+
+        let loaded = Matrix::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.routes().len(), 2);
+        for (a, b) in loaded.routes().iter().zip(matrix.routes()) {
+            assert_eq!(a.source, b.source);
+            assert_eq!(a.dest, b.dest);
+            assert_eq!((a.depth, a.offset, a.curve, a.enabled), (b.depth, b.offset, b.curve, b.enabled));
+        }
+    }
+}
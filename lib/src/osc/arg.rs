@@ -0,0 +1,72 @@
+use rosc::OscType;
+
+// Typed extraction from OSC arguments. Numeric types coerce between each other,
+// since controllers disagree on whether a fader is an int or a float
+pub trait FromArg: Sized {
+    fn from_arg(arg: &OscType) -> Option<Self>;
+}
+
+impl FromArg for f32 {
+    fn from_arg(arg: &OscType) -> Option<Self> {
+        match *arg {
+            OscType::Float(f) => Some(f),
+            OscType::Double(d) => Some(d as f32),
+            OscType::Int(i) => Some(i as f32),
+            OscType::Long(l) => Some(l as f32),
+            OscType::Bool(b) => Some(if b { 1.0 } else { 0.0 }),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for f64 {
+    fn from_arg(arg: &OscType) -> Option<Self> {
+        match *arg {
+            OscType::Double(d) => Some(d),
+            _ => f32::from_arg(arg).map(|f| f as f64),
+        }
+    }
+}
+
+impl FromArg for i32 {
+    fn from_arg(arg: &OscType) -> Option<Self> {
+        match *arg {
+            OscType::Int(i) => Some(i),
+            OscType::Long(l) => Some(l as i32),
+            OscType::Float(f) => Some(f as i32),
+            OscType::Double(d) => Some(d as i32),
+            OscType::Bool(b) => Some(b as i32),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for i64 {
+    fn from_arg(arg: &OscType) -> Option<Self> {
+        match *arg {
+            OscType::Long(l) => Some(l),
+            _ => i32::from_arg(arg).map(|i| i as i64),
+        }
+    }
+}
+
+// Buttons on most tablets send 0.0 / 1.0
+impl FromArg for bool {
+    fn from_arg(arg: &OscType) -> Option<Self> {
+        match *arg {
+            OscType::Bool(b) => Some(b),
+            OscType::Int(i) => Some(i != 0),
+            OscType::Float(f) => Some(f >= 0.5),
+            _ => None,
+        }
+    }
+}
+
+impl FromArg for String {
+    fn from_arg(arg: &OscType) -> Option<Self> {
+        match arg {
+            OscType::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use super::Message;
+
+// The Mixxx controller mapping in mixxx/ sends deck state as MIDI, which arrives here
+// bridged to OSC as /midi/cc<n> and /midi/noteon<n> with the 7 bit value as the first argument.
+// Deck `d` uses CCs from 16 + 8d:
+//   +1 BPM low, +2 BPM hundredths, +3 BPM high (bpm = 127 * high + low + 0.01 * hundredths)
+//   +4 position coarse, +5 position fine (14 bit, fraction of the track)
+//   +6 play state, +7 track counter, bumped whenever a track is loaded
+// Beats are note on 50 + d, the crossfader is CC 15.
+pub const DECKS: usize = 4;
+
+const CROSSFADER: u8 = 15;
+const BEAT: u8 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MixxxEvent {
+    Bpm(usize, f32),
+    Beat(usize),
+    Position(usize, f32),
+    Play(usize, bool),
+    TrackChange(usize),
+    // Crossfader from -1.0 (left) to 1.0 (right)
+    Crossfader(f32),
+    // The deck the audience is mostly hearing changed
+    Lead(Option<usize>),
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Deck {
+    pub bpm: f32,
+    // Fraction of the track played
+    pub position: f32,
+    pub playing: bool,
+    pub track: u32,
+    bpm_parts: (Option<f32>, Option<f32>, Option<f32>),
+    position_msb: f32,
+    counter: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mixxx {
+    pub decks: [Deck; DECKS],
+    pub crossfader: f32,
+    lead: Option<usize>,
+    // Deck that last reported a BPM, for scripts that don't send play state
+    last: Option<usize>,
+}
+
+enum Control {
+    Cc(u8),
+    Note(u8),
+}
+
+impl Mixxx {
+    // Deck the crossfader favours among those playing. Decks 0 and 2 are on the left
+    pub fn lead(&self) -> Option<usize> {
+        self.lead
+    }
+
+    // BPM of the lead deck, or of the last deck to report one if none are playing
+    pub fn bpm(&self) -> Option<f32> {
+        self.lead.or(self.last).map(|d| self.decks[d].bpm).filter(|&bpm| bpm > 0.0)
+    }
+
+    // Update from a bridged message, returning what changed. None if it isn't from Mixxx
+    pub fn process(&mut self, msg: &Message) -> Option<Vec<MixxxEvent>> {
+        let control = if let Some(n) = msg.addr.strip_prefix("/midi/cc") {
+            Control::Cc(n.parse().ok()?)
+        } else if let Some(n) = msg.addr.strip_prefix("/midi/noteon") {
+            Control::Note(n.parse().ok()?)
+        } else {
+            return None;
+        };
+
+        let v = msg.arg::<f32>(0).unwrap_or(0.0);
+
+        let mut events = Vec::new();
+        match control {
+            Control::Note(n) if n >= BEAT && ((n - BEAT) as usize) < DECKS => {
+                events.push(MixxxEvent::Beat((n - BEAT) as usize));
+            }
+            Control::Cc(CROSSFADER) => {
+                self.crossfader = v / 63.5 - 1.0;
+                events.push(MixxxEvent::Crossfader(self.crossfader));
+            }
+            Control::Cc(n) if n > 16 && ((n - 16) / 8) < DECKS as u8 => {
+                let d = ((n - 16) / 8) as usize;
+                if let Some(e) = self.deck(d, (n - 16) % 8, v) {
+                    if let MixxxEvent::Bpm(..) = e {
+                        self.last = Some(d);
+                    }
+                    events.push(e);
+                }
+            }
+            _ => return None,
+        }
+
+        let lead = self.find_lead();
+        if lead != self.lead {
+            self.lead = lead;
+            events.push(MixxxEvent::Lead(lead));
+        }
+
+        Some(events)
+    }
+
+    fn deck(&mut self, d: usize, offset: u8, v: f32) -> Option<MixxxEvent> {
+        let deck = &mut self.decks[d];
+
+        match offset {
+            1 => deck.bpm_parts.1 = Some(v),
+            2 => deck.bpm_parts.2 = Some(v),
+            3 => deck.bpm_parts.0 = Some(v),
+            4 => deck.position_msb = v,
+            5 => {
+                deck.position = (deck.position_msb * 128.0 + v) / 16383.0;
+                return Some(MixxxEvent::Position(d, deck.position));
+            }
+            6 => {
+                let playing = v >= 64.0;
+                if playing != deck.playing {
+                    deck.playing = playing;
+                    return Some(MixxxEvent::Play(d, playing));
+                }
+            }
+            7 => {
+                // The first counter value is the track that was loaded before we connected
+                let changed = deck.counter.map_or(false, |c| c != v);
+                deck.counter = Some(v);
+                if changed {
+                    deck.track += 1;
+                    return Some(MixxxEvent::TrackChange(d));
+                }
+            }
+            _ => {}
+        }
+
+        if let (Some(hi), Some(lo), Some(dec)) = deck.bpm_parts {
+            deck.bpm = (127.0 * hi) + lo + (0.01 * dec);
+            deck.bpm_parts = (None, None, None);
+            return Some(MixxxEvent::Bpm(d, deck.bpm));
+        }
+
+        None
+    }
+
+    fn find_lead(&self) -> Option<usize> {
+        let weight = |d: usize| {
+            if d % 2 == 0 {
+                1.0 - self.crossfader
+            } else {
+                1.0 + self.crossfader
+            }
+        };
+
+        let mut lead: Option<usize> = None;
+        for d in (0..DECKS).filter(|&d| self.decks[d].playing) {
+            lead = match lead {
+                // Ties keep the current lead, so a centred crossfader doesn't flicker
+                Some(l) if weight(l) > weight(d) || (weight(l) == weight(d) && Some(l) == self.lead) => Some(l),
+                _ => Some(d),
+            };
+        }
+
+        lead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rosc::OscType;
+
+    fn cc(mixxx: &mut Mixxx, n: u8, v: f32) -> Vec<MixxxEvent> {
+        let msg = Message::new(&format!("/midi/cc{}", n), vec![OscType::Float(v)]);
+        mixxx.process(&msg).unwrap()
+    }
+
+    #[test]
+    fn bpm_from_three_parts() {
+        let mut mixxx = Mixxx::default();
+
+        // 128.5 on deck 0, completed by whichever part arrives last
+        assert!(cc(&mut mixxx, 17, 1.0).is_empty());
+        assert!(cc(&mut mixxx, 18, 50.0).is_empty());
+        let events = cc(&mut mixxx, 19, 1.0);
+        assert!(matches!(events[..], [MixxxEvent::Bpm(0, bpm)] if (bpm - 128.5).abs() < 1e-4));
+
+        // 174 on deck 2, in another order
+        assert!(cc(&mut mixxx, 35, 1.0).is_empty());
+        assert!(cc(&mut mixxx, 33, 47.0).is_empty());
+        assert_eq!(cc(&mut mixxx, 34, 0.0), vec![MixxxEvent::Bpm(2, 174.0)]);
+
+        // Nothing playing, so the last deck to report wins
+        assert_eq!(mixxx.bpm(), Some(174.0));
+    }
+
+    #[test]
+    fn lead_follows_crossfader() {
+        let mut mixxx = Mixxx::default();
+        mixxx.decks[0].bpm = 120.0;
+        mixxx.decks[1].bpm = 140.0;
+
+        // Hard left
+        cc(&mut mixxx, CROSSFADER, 0.0);
+        assert_eq!(cc(&mut mixxx, 22, 127.0), vec![MixxxEvent::Play(0, true), MixxxEvent::Lead(Some(0))]);
+        assert_eq!(cc(&mut mixxx, 30, 127.0), vec![MixxxEvent::Play(1, true)]);
+        assert_eq!(mixxx.bpm(), Some(120.0));
+
+        // Over to the right
+        let events = cc(&mut mixxx, CROSSFADER, 127.0);
+        assert!(events.contains(&MixxxEvent::Lead(Some(1))));
+        assert_eq!(mixxx.bpm(), Some(140.0));
+
+        // Stopping the lead deck hands over to whatever is still playing
+        cc(&mut mixxx, 30, 0.0);
+        assert_eq!(mixxx.lead(), Some(0));
+        cc(&mut mixxx, 22, 0.0);
+        assert_eq!(mixxx.lead(), None);
+    }
+
+    #[test]
+    fn position_and_tracks() {
+        let mut mixxx = Mixxx::default();
+
+        cc(&mut mixxx, 20, 64.0);
+        let events = cc(&mut mixxx, 21, 0.0);
+        assert!(matches!(events[0], MixxxEvent::Position(0, p) if (p - 0.5).abs() < 1e-3));
+
+        // The first counter is whatever was loaded before we connected
+        assert!(cc(&mut mixxx, 23, 3.0).is_empty());
+        assert_eq!(cc(&mut mixxx, 23, 4.0), vec![MixxxEvent::TrackChange(0)]);
+    }
+
+    #[test]
+    fn ignores_other_messages() {
+        let mut mixxx = Mixxx::default();
+        assert!(mixxx.process(&Message::new("/fx/edge", vec![])).is_none());
+        assert!(mixxx.process(&Message::new("/midi/cc100", vec![OscType::Float(1.0)])).is_none());
+    }
+}
@@ -0,0 +1,207 @@
+use crossbeam_queue::SegQueue;
+use rosc::OscPacket;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+pub mod pattern;
+mod arg;
+mod send;
+mod query;
+mod mixxx;
+mod schedule;
+
+pub use arg::FromArg;
+pub use send::Sender;
+pub use query::{apply, Query};
+pub use mixxx::{Deck, Mixxx, MixxxEvent};
+pub use schedule::Scheduler;
+pub use rosc::OscType;
+
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub addr: String,
+    pub args: Vec<OscType>,
+    // Where a received message came from
+    pub from: Option<SocketAddr>,
+}
+
+impl Message {
+    pub fn new(addr: &str, args: Vec<OscType>) -> Self {
+        Self {
+            addr: addr.to_string(),
+            args,
+            from: None,
+        }
+    }
+
+    // Whether this message's address matches an OSC address pattern, e.g. "/fx/*/amount"
+    pub fn matches(&self, pattern: &str) -> bool {
+        pattern::matches(pattern, &self.addr)
+    }
+
+    pub fn arg<T: FromArg>(&self, i: usize) -> Option<T> {
+        self.args.get(i).and_then(T::from_arg)
+    }
+}
+
+#[derive(Debug)]
+pub enum OscMessage {
+    // Tempo of the deck Mixxx is mostly playing
+    Bpm(f32),
+    Mixxx(MixxxEvent),
+    Message(Message),
+}
+
+// Messages with the time they're due, if they came in a future-dated bundle
+type OscQueue = Arc<SegQueue<(Option<SystemTime>, Message)>>;
+type Handler = Box<dyn FnMut(&Message)>;
+
+pub struct Osc {
+    pub mixxx: Mixxx,
+    queue: OscQueue,
+    scheduler: Scheduler,
+    handlers: Vec<(String, Handler)>,
+    // Handled addresses with their type tags and docs, for OSCQuery
+    docs: Vec<(String, &'static str, String)>,
+    query: Option<Query>,
+}
+
+impl Osc {
+    pub fn init(port: u16) -> Self {
+        let queue = Arc::new(SegQueue::new());
+        let socket = UdpSocket::bind(("0.0.0.0", port)).expect("Failed to bind OSC socket");
+
+        let rqueue = Arc::clone(&queue);
+        thread::spawn(move || {
+            receive(&socket, rqueue);
+        });
+
+        Self {
+            mixxx: Mixxx::default(),
+            queue,
+            scheduler: Scheduler::default(),
+            handlers: Vec::new(),
+            docs: Vec::new(),
+            query: None,
+        }
+    }
+
+    // Call `handler` from poll() for every message matching `pattern`.
+    // Patterns can appear on either side, as the spec puts them in the incoming address.
+    // Plain addresses are served through describe() with the type tags `typ` and `doc`
+    pub fn on<F: FnMut(&Message) + 'static>(&mut self, pattern: &str, typ: &'static str, doc: &str, handler: F) {
+        self.handlers.push((pattern.to_string(), Box::new(handler)));
+
+        // A pattern isn't an address a client could list
+        if !pattern::is_pattern(pattern) {
+            if let Some(query) = &self.query {
+                query.add(pattern, typ, doc);
+            }
+            self.docs.push((pattern.to_string(), typ, doc.to_string()));
+        }
+    }
+
+    // Describe the handled addresses to OSCQuery clients, including handlers added later
+    pub fn describe(&mut self, query: Query) {
+        for (addr, typ, doc) in self.docs.iter() {
+            query.add(addr, typ, doc);
+        }
+        self.query = Some(query);
+    }
+
+    // Run handlers for messages due this frame, then hand them all back for matching in update()
+    pub fn poll(&mut self) -> Vec<OscMessage> {
+        // Untimed messages are due straight away
+        while let Some((t, msg)) = self.queue.pop() {
+            self.scheduler.push(t.unwrap_or(SystemTime::UNIX_EPOCH), msg);
+        }
+
+        let due = self.scheduler.due();
+        let mut messages = Vec::with_capacity(due.len());
+
+        for msg in due {
+            // Handlers see everything, including what the Mixxx bridge decodes
+            self.dispatch(&msg);
+
+            let bpm = self.mixxx.bpm();
+
+            match self.mixxx.process(&msg) {
+                Some(events) => {
+                    messages.extend(events.into_iter().map(OscMessage::Mixxx));
+
+                    if let Some(lead) = self.mixxx.bpm().filter(|&b| Some(b) != bpm) {
+                        messages.push(OscMessage::Bpm(lead));
+                    }
+                }
+                None => messages.push(OscMessage::Message(msg)),
+            }
+        }
+
+        messages
+    }
+
+    fn dispatch(&mut self, msg: &Message) {
+        for (pat, handler) in self.handlers.iter_mut() {
+            if pattern::matches(pat, &msg.addr) || pattern::matches(&msg.addr, pat) {
+                handler(msg);
+            }
+        }
+    }
+}
+
+// Bundles are flattened in order, each message tagged with its innermost bundle's time
+fn flatten(
+    packet: OscPacket,
+    from: SocketAddr,
+    t: Option<SystemTime>,
+    out: &mut Vec<(Option<SystemTime>, Message)>,
+) {
+    match packet {
+        OscPacket::Message(m) => out.push((
+            t,
+            Message {
+                addr: m.addr,
+                args: m.args,
+                from: Some(from),
+            },
+        )),
+        OscPacket::Bundle(b) => {
+            let t = schedule::to_system(b.timetag).or(t);
+            for p in b.content {
+                flatten(p, from, t, out);
+            }
+        }
+    }
+}
+
+fn receive(socket: &UdpSocket, queue: OscQueue) {
+    let mut buf = [0u8; rosc::decoder::MTU];
+
+    loop {
+        let (n, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) => {
+                log::error!("OSC receive failed: {}", e);
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+        };
+
+        let packet = match rosc::decoder::decode(&buf[..n]) {
+            Ok(packet) => packet,
+            Err(e) => {
+                log::warn!("Dropping malformed OSC packet: {:?}", e);
+                continue;
+            }
+        };
+
+        let mut msgs = Vec::new();
+        flatten(packet, from, None, &mut msgs);
+
+        for msg in msgs {
+            queue.push(msg);
+        }
+    }
+}
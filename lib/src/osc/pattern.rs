@@ -0,0 +1,136 @@
+// OSC 1.0 address pattern matching, one address part at a time:
+//   ?        any single character
+//   *        any sequence of characters
+//   [a-z]    any character in the set, [!a-z] negates
+//   {foo,bar} any of the comma separated strings
+pub fn matches(pattern: &str, addr: &str) -> bool {
+    let mut ps = pattern.split('/');
+    let mut xs = addr.split('/');
+
+    loop {
+        match (ps.next(), xs.next()) {
+            (None, None) => return true,
+            (Some(p), Some(x)) => {
+                let p = p.chars().collect::<Vec<_>>();
+                let x = x.chars().collect::<Vec<_>>();
+                if !part(&p, &x) {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+// Whether an address contains any pattern characters
+pub fn is_pattern(addr: &str) -> bool {
+    addr.chars().any(|c| matches!(c, '?' | '*' | '[' | '{'))
+}
+
+fn part(p: &[char], s: &[char]) -> bool {
+    match p.first() {
+        None => s.is_empty(),
+        Some('*') => (0..=s.len()).any(|i| part(&p[1..], &s[i..])),
+        Some('?') => !s.is_empty() && part(&p[1..], &s[1..]),
+        Some('[') => {
+            let end = match p.iter().position(|&c| c == ']') {
+                Some(end) => end,
+                None => return false,
+            };
+
+            !s.is_empty() && set(&p[1..end], s[0]) && part(&p[end + 1..], &s[1..])
+        }
+        Some('{') => {
+            let end = match p.iter().position(|&c| c == '}') {
+                Some(end) => end,
+                None => return false,
+            };
+            let rest = &p[end + 1..];
+
+            p[1..end]
+                .split(|&c| c == ',')
+                .any(|alt| s.starts_with(alt) && part(rest, &s[alt.len()..]))
+        }
+        Some(&c) => s.first() == Some(&c) && part(&p[1..], &s[1..]),
+    }
+}
+
+fn set(set: &[char], c: char) -> bool {
+    let (negate, set) = match set.first() {
+        Some('!') => (true, &set[1..]),
+        _ => (false, set),
+    };
+
+    let mut found = false;
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            found |= set[i] <= c && c <= set[i + 2];
+            i += 3;
+        } else {
+            found |= set[i] == c;
+            i += 1;
+        }
+    }
+
+    found != negate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn literal() {
+        assert!(matches("/fx/edge", "/fx/edge"));
+        assert!(!matches("/fx/edge", "/fx/edges"));
+        assert!(!matches("/fx", "/fx/edge"));
+    }
+
+    #[test]
+    fn star() {
+        assert!(matches("/fx/*/amount", "/fx/blur/amount"));
+        assert!(matches("/fx/b*r", "/fx/blur"));
+        assert!(matches("/fx/*", "/fx/"));
+        // Only within one part of the address
+        assert!(!matches("/fx/*", "/fx/blur/amount"));
+    }
+
+    #[test]
+    fn question_mark() {
+        assert!(matches("/deck?", "/deck1"));
+        assert!(!matches("/deck?", "/deck"));
+        assert!(!matches("/deck?", "/deck12"));
+    }
+
+    #[test]
+    fn char_set() {
+        assert!(matches("/ch[0-3]", "/ch2"));
+        assert!(!matches("/ch[0-3]", "/ch4"));
+        assert!(matches("/ch[ab7]", "/ch7"));
+        assert!(matches("/ch[a-c0-3]", "/chb"));
+        assert!(!matches("/ch[0-3]", "/ch"));
+    }
+
+    #[test]
+    fn negated_set() {
+        assert!(matches("/ch[!0-3]", "/ch7"));
+        assert!(!matches("/ch[!0-3]", "/ch1"));
+        assert!(matches("/ch[!a-z]", "/ch_"));
+    }
+
+    #[test]
+    fn alternatives() {
+        assert!(matches("/fx/{blur,edge}/on", "/fx/edge/on"));
+        assert!(!matches("/fx/{blur,edge}/on", "/fx/vhs/on"));
+        // Backtracks when the first alternative is a prefix of another
+        assert!(matches("/{a,ab}c", "/abc"));
+    }
+
+    #[test]
+    fn detects_patterns() {
+        assert!(is_pattern("/fx/*"));
+        assert!(is_pattern("/ch[0-3]"));
+        assert!(!is_pattern("/fx/edge"));
+    }
+}
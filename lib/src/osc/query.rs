@@ -0,0 +1,338 @@
+use serde_json::{json, Map, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::Message;
+use crate::param::Params;
+
+// OSCQuery access flags
+const READ: u8 = 1;
+const WRITE: u8 = 2;
+
+// Clients that stall longer than this are dropped
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+struct Entry {
+    addr: String,
+    typ: &'static str,
+    doc: String,
+    access: u8,
+    value: Option<f32>,
+    range: Option<(f32, f32)>,
+}
+
+struct State {
+    name: String,
+    osc_port: u16,
+    params: Vec<Entry>,
+    extra: Vec<Entry>,
+}
+
+// Describes every OSC-controllable address over HTTP+JSON following the OSCQuery
+// conventions, so controller apps can build a surface for a sketch on their own.
+// Parameters are served under /param/<name>, with '/' in names nesting
+#[derive(Clone)]
+pub struct Query {
+    state: Arc<Mutex<State>>,
+}
+
+impl Query {
+    // Serve on `http_port`, advertising `osc_port` as where messages should be sent
+    pub fn init(name: &str, http_port: u16, osc_port: u16) -> Self {
+        let state = Arc::new(Mutex::new(State {
+            name: name.to_string(),
+            osc_port,
+            params: Vec::new(),
+            extra: Vec::new(),
+        }));
+
+        let listener = TcpListener::bind(("0.0.0.0", http_port)).expect("Failed to bind OSCQuery server");
+
+        let rstate = Arc::clone(&state);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        // A thread each, so a slow or idle client can't hold up discovery
+                        let state = Arc::clone(&rstate);
+                        thread::spawn(move || {
+                            if let Err(e) = serve(stream, &state) {
+                                log::warn!("OSCQuery: {}", e);
+                            }
+                        });
+                    }
+                    Err(e) => log::warn!("OSCQuery: Connection failed: {}", e),
+                }
+            }
+        });
+
+        Self { state }
+    }
+
+    // Describe an address that isn't a parameter, e.g. a trigger. `typ` is an OSC type tag string
+    pub fn add(&self, addr: &str, typ: &'static str, doc: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.extra.retain(|e| e.addr != addr);
+        state.extra.push(Entry {
+            addr: addr.to_string(),
+            typ,
+            doc: doc.to_string(),
+            access: WRITE,
+            value: None,
+            range: None,
+        });
+    }
+
+    // Refresh the served parameters and their current values
+    pub fn update(&self, params: &Params) {
+        let mut entries = params
+            .iter()
+            .map(|(name, p)| Entry {
+                addr: format!("/param/{}", name),
+                typ: "f",
+                doc: p.doc.to_string(),
+                access: READ | WRITE,
+                value: Some(p.v),
+                range: Some((p.min, p.max)),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.addr.cmp(&b.addr));
+
+        self.state.lock().unwrap().params = entries;
+    }
+}
+
+// Apply a `/param/<name> <v>` message. Returns whether it named a parameter
+pub fn apply(msg: &Message, params: &mut Params) -> bool {
+    let name = match msg.addr.strip_prefix("/param/") {
+        Some(name) if params.contains(name) => name,
+        _ => return false,
+    };
+
+    match msg.arg::<f32>(0) {
+        Some(v) => params.set(name, v),
+        None => log::warn!("OSC: {} needs a numeric argument", msg.addr),
+    }
+
+    true
+}
+
+fn serve(mut stream: TcpStream, state: &Mutex<State>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    // Drain the headers, nothing in them matters here
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let target = line.split_whitespace().nth(1).unwrap_or("/");
+    let (path, attr) = match target.find('?') {
+        Some(i) => (&target[..i], Some(&target[i + 1..])),
+        None => (target, None),
+    };
+
+    let body = {
+        let state = state.lock().unwrap();
+        respond(&state, path, attr)
+    };
+
+    match body {
+        Some(body) => {
+            let body = body.to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+        }
+        None => write!(stream, "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"),
+    }
+}
+
+fn respond(state: &State, path: &str, attr: Option<&str>) -> Option<Value> {
+    if attr == Some("HOST_INFO") {
+        return Some(json!({
+            "NAME": state.name,
+            "OSC_PORT": state.osc_port,
+            "OSC_TRANSPORT": "UDP",
+            "EXTENSIONS": {
+                "ACCESS": true,
+                "VALUE": true,
+                "RANGE": true,
+                "DESCRIPTION": true,
+            },
+        }));
+    }
+
+    let path = if path.len() > 1 { path.trim_end_matches('/') } else { path };
+
+    let mut root = container("/");
+    for entry in state.params.iter().chain(state.extra.iter()) {
+        insert(&mut root, entry);
+    }
+
+    let node = find(&root, path)?;
+
+    match attr {
+        None => Some(node.clone()),
+        Some(attr) => node.get(attr).map(|v| {
+            let mut value = Map::new();
+            value.insert(attr.to_string(), v.clone());
+            Value::Object(value)
+        }),
+    }
+}
+
+fn container(path: &str) -> Value {
+    json!({
+        "FULL_PATH": path,
+        "ACCESS": 0,
+        "CONTENTS": {},
+    })
+}
+
+fn leaf(entry: &Entry) -> Value {
+    let mut node = Map::new();
+    node.insert("FULL_PATH".into(), json!(entry.addr));
+    node.insert("TYPE".into(), json!(entry.typ));
+    node.insert("ACCESS".into(), json!(entry.access));
+    node.insert("DESCRIPTION".into(), json!(entry.doc));
+
+    if let Some(v) = entry.value {
+        node.insert("VALUE".into(), json!([v]));
+    }
+
+    if let Some((min, max)) = entry.range {
+        node.insert("RANGE".into(), json!([{ "MIN": min, "MAX": max }]));
+    }
+
+    Value::Object(node)
+}
+
+fn insert(root: &mut Value, entry: &Entry) {
+    let parts = entry.addr.split('/').filter(|p| !p.is_empty()).collect::<Vec<_>>();
+
+    let mut node = root;
+    let mut path = String::new();
+    for (i, part) in parts.iter().enumerate() {
+        path.push('/');
+        path.push_str(part);
+
+        let contents = match node.get_mut("CONTENTS").and_then(Value::as_object_mut) {
+            Some(contents) => contents,
+            // A leaf and a container share an address, the leaf wins
+            None => return,
+        };
+
+        if i + 1 == parts.len() {
+            contents.insert(part.to_string(), leaf(entry));
+            return;
+        }
+
+        node = contents
+            .entry(part.to_string())
+            .or_insert_with(|| container(&path));
+    }
+}
+
+fn find<'a>(root: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('/')
+        .filter(|p| !p.is_empty())
+        .try_fold(root, |node, part| node.get("CONTENTS")?.get(part))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::osc::Osc;
+    use crate::param::Param;
+
+    fn query() -> Query {
+        Query {
+            state: Arc::new(Mutex::new(State {
+                name: "sketch".to_string(),
+                osc_port: 9000,
+                params: Vec::new(),
+                extra: Vec::new(),
+            })),
+        }
+    }
+
+    fn get(query: &Query, path: &str, attr: Option<&str>) -> Option<Value> {
+        respond(&query.state.lock().unwrap(), path, attr)
+    }
+
+    #[test]
+    fn host_info() {
+        let info = get(&query(), "/", Some("HOST_INFO")).unwrap();
+        assert_eq!(info["NAME"], "sketch");
+        assert_eq!(info["OSC_PORT"], 9000);
+        assert_eq!(info["OSC_TRANSPORT"], "UDP");
+    }
+
+    #[test]
+    fn nests_params() {
+        let query = query();
+        let params = Params::default()
+            .with("fx/edge", Param::new(0.5, 0.0, 2.0).doc("Edge glow"))
+            .with("zoom", Param::new(1.0, 0.0, 4.0));
+        query.update(&params);
+        query.add("/flash", "", "Flash the screen");
+
+        let root = get(&query, "/", None).unwrap();
+        assert_eq!(root["FULL_PATH"], "/");
+        assert!(root["CONTENTS"]["flash"].is_object());
+        assert!(root["CONTENTS"]["param"]["CONTENTS"]["zoom"].is_object());
+
+        let fx = get(&query, "/param/fx/", None).unwrap();
+        assert_eq!(fx["FULL_PATH"], "/param/fx");
+        assert_eq!(fx["ACCESS"], 0);
+
+        let edge = get(&query, "/param/fx/edge", None).unwrap();
+        assert_eq!(edge["TYPE"], "f");
+        assert_eq!(edge["ACCESS"], READ | WRITE);
+        assert_eq!(edge["DESCRIPTION"], "Edge glow");
+        assert_eq!(edge["VALUE"], json!([0.5]));
+        assert_eq!(edge["RANGE"], json!([{ "MIN": 0.0, "MAX": 2.0 }]));
+
+        assert!(get(&query, "/param/missing", None).is_none());
+    }
+
+    #[test]
+    fn attributes() {
+        let query = query();
+        query.update(&Params::default().with("zoom", Param::new(1.0, 0.0, 4.0)));
+
+        assert_eq!(get(&query, "/param/zoom", Some("VALUE")).unwrap(), json!({ "VALUE": [1.0] }));
+        assert_eq!(get(&query, "/param/zoom", Some("ACCESS")).unwrap(), json!({ "ACCESS": 3 }));
+        assert!(get(&query, "/param/zoom", Some("CRITICAL")).is_none());
+    }
+
+    #[test]
+    fn describes_handlers() {
+        let query = query();
+        let mut osc = Osc::init(0);
+
+        osc.on("/flash", "", "Flash the screen", |_| {});
+        osc.describe(query.clone());
+        osc.on("/scene", "s", "Switch scene by name", |_| {});
+        osc.on("/fx/*/amount", "f", "Not listed", |_| {});
+
+        let root = get(&query, "/", None).unwrap();
+        assert_eq!(root["CONTENTS"]["flash"]["DESCRIPTION"], "Flash the screen");
+        assert_eq!(root["CONTENTS"]["scene"]["TYPE"], "s");
+        assert!(root["CONTENTS"].get("fx").is_none());
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use super::Message;
+
+// Seconds between the NTP epoch (1900) and the Unix epoch
+const NTP_UNIX: u64 = 2_208_988_800;
+
+// Bundle timetag meaning "as soon as possible"
+pub const IMMEDIATELY: (u32, u32) = (0, 1);
+
+pub fn to_system(timetag: (u32, u32)) -> Option<SystemTime> {
+    if timetag == IMMEDIATELY {
+        return None;
+    }
+
+    let (secs, frac) = timetag;
+    let secs = (secs as u64).checked_sub(NTP_UNIX)?;
+    let nanos = ((frac as u64 * 1_000_000_000) >> 32) as u32;
+
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+pub fn from_system(t: SystemTime) -> (u32, u32) {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let frac = ((d.subsec_nanos() as u64) << 32) / 1_000_000_000;
+
+    ((d.as_secs() + NTP_UNIX) as u32, frac as u32)
+}
+
+// Holds future-dated bundle contents until the frame they land on.
+// A message is due on the frame closest to its timetag, estimating the
+// next frame from the time between polls
+#[derive(Default)]
+pub struct Scheduler {
+    pending: Vec<(SystemTime, Message)>,
+    last: Option<Instant>,
+}
+
+impl Scheduler {
+    pub fn push(&mut self, t: SystemTime, msg: Message) {
+        // After any message at the same time, so bundle order is kept
+        let i = self.pending.iter().position(|(p, _)| *p > t).unwrap_or(self.pending.len());
+        self.pending.insert(i, (t, msg));
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn due(&mut self) -> Vec<Message> {
+        let now = Instant::now();
+        let dt = self.last.map_or(Duration::default(), |last| now - last);
+        self.last = Some(now);
+
+        let horizon = SystemTime::now() + dt / 2;
+        let n = self.pending.iter().take_while(|(t, _)| *t <= horizon).count();
+
+        self.pending.drain(..n).map(|(_, msg)| msg).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs(msgs: Vec<Message>) -> Vec<String> {
+        msgs.into_iter().map(|m| m.addr).collect()
+    }
+
+    #[test]
+    fn timetags_round_trip() {
+        assert_eq!(from_system(UNIX_EPOCH), (NTP_UNIX as u32, 0));
+        assert_eq!(to_system((NTP_UNIX as u32 + 1, 1 << 31)), Some(UNIX_EPOCH + Duration::from_millis(1500)));
+
+        let t = UNIX_EPOCH + Duration::new(1_600_000_000, 123_456_789);
+        let back = to_system(from_system(t)).unwrap();
+        // Both ways truncate, so it can come back a nanosecond early
+        assert!(t.duration_since(back).unwrap() <= Duration::from_nanos(1));
+    }
+
+    #[test]
+    fn immediately() {
+        assert_eq!(to_system(IMMEDIATELY), None);
+        // Before the Unix epoch can't be a real time either
+        assert_eq!(to_system((1, 0)), None);
+        assert!(to_system((NTP_UNIX as u32, 0)).is_some());
+    }
+
+    #[test]
+    fn due_in_order() {
+        let mut scheduler = Scheduler::default();
+        let now = SystemTime::now();
+
+        scheduler.push(now + Duration::from_secs(60), Message::new("/later", vec![]));
+        scheduler.push(UNIX_EPOCH, Message::new("/b", vec![]));
+        scheduler.push(UNIX_EPOCH, Message::new("/c", vec![]));
+        scheduler.push(now - Duration::from_secs(1), Message::new("/d", vec![]));
+        scheduler.push(UNIX_EPOCH - Duration::from_secs(1), Message::new("/a", vec![]));
+
+        assert_eq!(addrs(scheduler.due()), vec!["/a", "/b", "/c", "/d"]);
+        assert_eq!(scheduler.len(), 1);
+    }
+
+    #[test]
+    fn due_half_a_frame_early() {
+        let mut scheduler = Scheduler::default();
+        scheduler.due();
+        std::thread::sleep(Duration::from_millis(40));
+
+        // Closer to this frame than the next one, going by the last frame's length
+        let now = SystemTime::now();
+        scheduler.push(now + Duration::from_millis(5), Message::new("/soon", vec![]));
+        scheduler.push(now + Duration::from_secs(1), Message::new("/later", vec![]));
+
+        assert_eq!(addrs(scheduler.due()), vec!["/soon"]);
+        assert!(!scheduler.is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+use rosc::{OscBundle, OscPacket, OscType};
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::SystemTime;
+
+use super::{schedule, Message};
+use crate::audio::Audio;
+use crate::param::Params;
+
+// Frequency bands broadcast as /audio/bands
+const BANDS: [(f32, f32); 3] = [(20.0, 250.0), (250.0, 4000.0), (4000.0, 20000.0)];
+
+// Pushes state out to any number of subscribed destinations,
+// e.g. TouchOSC tablets, lighting consoles or SuperCollider
+pub struct Sender {
+    socket: UdpSocket,
+    targets: Vec<SocketAddr>,
+    // Whether /subscribe may name a host other than the sender's
+    remote: bool,
+    // Last value broadcast per parameter, so unchanged ones aren't resent every frame
+    sent: HashMap<String, f32>,
+}
+
+impl Sender {
+    pub fn new() -> Self {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).expect("Failed to bind OSC socket");
+
+        Self {
+            socket,
+            targets: Vec::new(),
+            remote: false,
+            sent: HashMap::new(),
+        }
+    }
+
+    pub fn subscribe<A: ToSocketAddrs>(&mut self, addr: A) {
+        let addrs = match addr.to_socket_addrs() {
+            Ok(addrs) => addrs,
+            Err(e) => return log::warn!("Can't resolve OSC destination: {}", e),
+        };
+
+        for addr in addrs {
+            if !self.targets.contains(&addr) {
+                log::info!("OSC: Sending to {}", addr);
+                self.targets.push(addr);
+                // New subscribers need a full picture, not just the next change
+                self.sent.clear();
+            }
+        }
+    }
+
+    pub fn unsubscribe<A: ToSocketAddrs>(&mut self, addr: A) {
+        if let Ok(addrs) = addr.to_socket_addrs() {
+            for addr in addrs {
+                self.targets.retain(|&t| t != addr);
+            }
+        }
+    }
+
+    // Let /subscribe name any host. Otherwise anyone on the network could
+    // point our output at a third party
+    pub fn allow_remote(mut self, remote: bool) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    // Lets remote ends manage themselves with `/subscribe [port]` and
+    // `/unsubscribe [port]`, sending back to the host the message came from.
+    // `/subscribe <host> <port>` only works with allow_remote(). Returns whether
+    // the message was consumed
+    pub fn handle(&mut self, msg: &Message) -> bool {
+        if msg.addr != "/subscribe" && msg.addr != "/unsubscribe" {
+            return false;
+        }
+
+        let from = match msg.from {
+            Some(from) => from,
+            None => return false,
+        };
+
+        let subscribe = msg.addr == "/subscribe";
+
+        if let (Some(host), Some(port)) = (msg.arg::<String>(0), msg.arg::<i32>(1)) {
+            if !self.remote {
+                log::warn!("OSC: Ignoring {} for {} from {}", msg.addr, host, from);
+            } else if subscribe {
+                self.subscribe((host.as_str(), port as u16));
+            } else {
+                self.unsubscribe((host.as_str(), port as u16));
+            }
+            return true;
+        }
+
+        let port = match msg.args.first() {
+            Some(_) => msg.arg::<i32>(0).filter(|&p| p > 0 && p <= u16::MAX as i32),
+            None => Some(from.port() as i32),
+        };
+        let dest = match port {
+            Some(port) => SocketAddr::new(from.ip(), port as u16),
+            None => {
+                log::warn!("OSC: Invalid port in {} from {}", msg.addr, from);
+                return true;
+            }
+        };
+
+        if subscribe {
+            self.subscribe(dest);
+        } else {
+            self.unsubscribe(dest);
+        }
+
+        true
+    }
+
+    pub fn targets(&self) -> &[SocketAddr] {
+        &self.targets
+    }
+
+    pub fn send(&self, msg: Message) {
+        self.packet(&OscPacket::Message(rosc::OscMessage {
+            addr: msg.addr,
+            args: msg.args,
+        }));
+    }
+
+    // Several messages delivered together, to be applied immediately
+    pub fn send_bundle(&self, msgs: Vec<Message>) {
+        self.bundle(schedule::IMMEDIATELY, msgs);
+    }
+
+    // Several messages to be applied together at `t`
+    pub fn send_at(&self, t: SystemTime, msgs: Vec<Message>) {
+        self.bundle(schedule::from_system(t), msgs);
+    }
+
+    fn bundle(&self, timetag: (u32, u32), msgs: Vec<Message>) {
+        let content = msgs
+            .into_iter()
+            .map(|m| {
+                OscPacket::Message(rosc::OscMessage {
+                    addr: m.addr,
+                    args: m.args,
+                })
+            })
+            .collect();
+
+        self.packet(&OscPacket::Bundle(OscBundle {
+            timetag,
+            content,
+        }));
+    }
+
+    pub fn bpm(&self, bpm: f32) {
+        self.send(Message::new("/bpm", vec![OscType::Float(bpm)]));
+    }
+
+    pub fn beat(&self) {
+        self.send(Message::new("/beat", vec![]));
+    }
+
+    pub fn feature(&self, name: &str, v: f32) {
+        self.send(Message::new(&format!("/feature/{}", name), vec![OscType::Float(v)]));
+    }
+
+    // Levels of the current audio frame
+    pub fn features(&self, audio: &Audio) {
+        let bands = BANDS
+            .iter()
+            .map(|&(f0, f1)| OscType::Float(audio.rms_range(f0, f1)))
+            .collect();
+
+        self.send_bundle(vec![
+            Message::new("/audio/rms", vec![OscType::Float(audio.rms())]),
+            Message::new("/audio/peak", vec![OscType::Float(audio.peak())]),
+            Message::new("/audio/bands", bands),
+        ]);
+    }
+
+    // Every parameter that changed since the last call, as /param/<name> <v>
+    pub fn params(&mut self, params: &Params) {
+        let mut msgs = Vec::new();
+
+        for (name, param) in params.iter() {
+            if self.sent.get(name) != Some(&param.v) {
+                self.sent.insert(name.to_string(), param.v);
+                msgs.push(Message::new(&format!("/param/{}", name), vec![OscType::Float(param.v)]));
+            }
+        }
+
+        if !msgs.is_empty() {
+            self.send_bundle(msgs);
+        }
+    }
+
+    fn packet(&self, packet: &OscPacket) {
+        if self.targets.is_empty() {
+            return;
+        }
+
+        let buf = match rosc::encoder::encode(packet) {
+            Ok(buf) => buf,
+            Err(e) => return log::error!("Failed to encode OSC packet: {:?}", e),
+        };
+
+        for target in &self.targets {
+            if let Err(e) = self.socket.send_to(&buf, target) {
+                log::warn!("OSC: Failed to send to {}: {}", target, e);
+            }
+        }
+    }
+}
+
+impl Default for Sender {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,409 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use crate::curve::Ease;
+
+// What a parameter's value represents. Ints and bools are still stored as f32,
+// but are kept on whole numbers
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Kind {
+    Float,
+    Int,
+    Bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Param {
+    pub v: f32,
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    pub kind: Kind,
+    pub curve: Ease,
+    // Time constant in ms for smoothing control changes, 0 to apply them immediately
+    pub slew: f32,
+    // What the control does, shown to remote control surfaces
+    pub doc: &'static str,
+    // For listing related parameters together, e.g. "fx"
+    pub group: &'static str,
+    target: Option<f32>,
+}
+
+impl Param {
+    pub fn new(v: f32, min: f32, max: f32) -> Self {
+        Self {
+            v,
+            min,
+            max,
+            default: v,
+            kind: Kind::Float,
+            curve: Ease::Linear,
+            slew: 0.0,
+            doc: "",
+            group: "",
+            target: None,
+        }
+    }
+
+    pub fn int(v: i32, min: i32, max: i32) -> Self {
+        Self {
+            kind: Kind::Int,
+            ..Self::new(v as f32, min as f32, max as f32)
+        }
+    }
+
+    pub fn toggle(on: bool) -> Self {
+        Self {
+            kind: Kind::Bool,
+            curve: Ease::Toggle,
+            ..Self::new(if on { 1.0 } else { 0.0 }, 0.0, 1.0)
+        }
+    }
+
+    pub fn curve(mut self, curve: Ease) -> Self {
+        self.curve = curve;
+        self
+    }
+
+    pub fn slew(mut self, t: f32) -> Self {
+        self.slew = t;
+        self
+    }
+
+    pub fn doc(mut self, doc: &'static str) -> Self {
+        self.doc = doc;
+        self
+    }
+
+    pub fn group(mut self, group: &'static str) -> Self {
+        self.group = group;
+        self
+    }
+
+    // Set directly, clamped to the range and rounded for ints and bools
+    pub fn set(&mut self, v: f32) {
+        self.v = self.quantize(v);
+        self.target = None;
+    }
+
+    pub fn reset(&mut self) {
+        self.set(self.default);
+    }
+
+    // Set from a normalized control position, applying the curve and range
+    pub fn set_normalized(&mut self, f: f32) {
+        let v = self.quantize(self.min + self.curve.apply(f) * (self.max - self.min));
+
+        if self.slew > 0.0 {
+            self.target = Some(v);
+        } else {
+            self.v = v;
+        }
+    }
+
+    // Normalized control position of the value, or of where it's slewing to
+    pub fn normalized(&self) -> f32 {
+        if self.max == self.min {
+            return 0.0;
+        }
+
+        let v = self.target.unwrap_or(self.v);
+        self.curve.invert((v - self.min) / (self.max - self.min))
+    }
+
+    pub fn f32(&self) -> f32 {
+        self.v
+    }
+
+    pub fn i32(&self) -> i32 {
+        self.v.round() as i32
+    }
+
+    pub fn bool(&self) -> bool {
+        self.v >= 0.5
+    }
+
+    fn quantize(&self, v: f32) -> f32 {
+        let (lo, hi) = (self.min.min(self.max), self.min.max(self.max));
+        let v = v.max(lo).min(hi);
+
+        match self.kind {
+            Kind::Float => v,
+            Kind::Int | Kind::Bool => v.round(),
+        }
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        if let Some(target) = self.target {
+            let k = 1.0 - (-(delta * 1000.0) / self.slew).exp();
+            self.v += (target - self.v) * k;
+
+            if (target - self.v).abs() <= (self.max - self.min).abs() * 1e-4 {
+                self.v = target;
+                self.target = None;
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Params {
+    map: HashMap<String, Param>,
+}
+
+impl Params {
+    pub fn with(mut self, name: &str, param: Param) -> Self {
+        self.map.insert(name.to_string(), param);
+        self
+    }
+
+    pub fn insert(&mut self, name: &str, param: Param) {
+        self.map.insert(name.to_string(), param);
+    }
+
+    pub fn v(&self, name: &str) -> f32 {
+        self.map.get(name).map_or(0.0, |p| p.v)
+    }
+
+    // Typed reads, the kind's zero value for unknown parameters
+    pub fn f32(&self, name: &str) -> f32 {
+        self.v(name)
+    }
+
+    pub fn i32(&self, name: &str) -> i32 {
+        self.map.get(name).map_or(0, Param::i32)
+    }
+
+    pub fn bool(&self, name: &str) -> bool {
+        self.map.get(name).map_or(false, Param::bool)
+    }
+
+    pub fn set(&mut self, name: &str, v: f32) {
+        match self.map.get_mut(name) {
+            Some(p) => p.set(v),
+            None => log::warn!("Unknown parameter '{}'", name),
+        }
+    }
+
+    pub fn reset(&mut self, name: &str) {
+        match self.map.get_mut(name) {
+            Some(p) => p.reset(),
+            None => log::warn!("Unknown parameter '{}'", name),
+        }
+    }
+
+    pub fn reset_all(&mut self) {
+        self.map.values_mut().for_each(Param::reset);
+    }
+
+    pub fn set_normalized(&mut self, name: &str, f: f32) {
+        match self.map.get_mut(name) {
+            Some(p) => p.set_normalized(f),
+            None => log::warn!("Unknown parameter '{}'", name),
+        }
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        self.map.values_mut().for_each(|p| p.update(delta));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Param> {
+        self.map.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Param> {
+        self.map.get_mut(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.map.contains_key(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Param)> {
+        self.map.iter().map(|(k, p)| (k.as_str(), p))
+    }
+
+    pub fn group<'a>(&'a self, group: &'a str) -> impl Iterator<Item = (&'a str, &'a Param)> {
+        self.iter().filter(move |(_, p)| p.group == group)
+    }
+}
+
+// Conversion from a parameter's value into a uniform field
+pub trait FromParam {
+    fn from_param(v: f32) -> Self;
+}
+
+impl FromParam for f32 {
+    fn from_param(v: f32) -> Self {
+        v
+    }
+}
+
+impl FromParam for i32 {
+    fn from_param(v: f32) -> Self {
+        v.round() as i32
+    }
+}
+
+impl FromParam for u32 {
+    fn from_param(v: f32) -> Self {
+        v.round().max(0.0) as u32
+    }
+}
+
+impl FromParam for bool {
+    fn from_param(v: f32) -> Self {
+        v >= 0.5
+    }
+}
+
+// A struct with fields that follow parameters, usually a uniform. Implement with bind!
+pub trait Bind {
+    // Copy in every bound parameter that `get` knows about
+    fn bind_with(&mut self, get: &dyn Fn(&str) -> Option<f32>);
+
+    fn bind(&mut self, params: &Params) {
+        self.bind_with(&|name| params.get(name).map(|p| p.v));
+    }
+}
+
+// Bind struct fields to parameters by name:
+//   bind!(FxState { edge: "fx/edge", glitch: "fx/glitch" });
+// then `state.bind(&params)` every frame, which also works through a UniformStorage
+#[macro_export]
+macro_rules! bind {
+    ($ty:ty { $($field:ident: $name:expr),* $(,)? }) => {
+        impl $crate::param::Bind for $ty {
+            fn bind_with(&mut self, get: &dyn Fn(&str) -> Option<f32>) {
+                $(
+                    if let Some(v) = get($name) {
+                        self.$field = $crate::param::FromParam::from_param(v);
+                    }
+                )*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn curves_round_trip() {
+        let curves = [
+            Ease::Linear,
+            Ease::Exp,
+            Ease::Log,
+            Ease::Pow(2.0),
+            Ease::Pow(0.5),
+            Ease::InOutSine,
+            Ease::OutExp(4.0),
+        ];
+
+        for &curve in curves.iter() {
+            let mut p = Param::new(0.0, -2.0, 10.0).curve(curve);
+            for &f in [0.0, 0.1, 0.5, 0.9, 1.0].iter() {
+                p.set_normalized(f);
+                assert!((p.normalized() - f).abs() < 1e-3, "{:?} at {}", curve, f);
+            }
+        }
+
+        // Audio taper, half way up is well under half the range
+        let mut p = Param::new(0.0, 0.0, 1.0).curve(Ease::Exp);
+        p.set_normalized(0.5);
+        assert!(p.v < 0.05);
+
+        let mut p = Param::toggle(false);
+        p.set_normalized(0.4);
+        assert!(!p.bool());
+        p.set_normalized(0.6);
+        assert!(p.bool());
+        assert_eq!(p.normalized(), 1.0);
+    }
+
+    #[test]
+    fn quantize() {
+        let mut p = Param::int(3, 0, 10);
+        p.set(4.6);
+        assert_eq!(p.i32(), 5);
+        assert_eq!(p.v, 5.0);
+        p.set(20.0);
+        assert_eq!(p.v, 10.0);
+        p.set_normalized(0.44);
+        assert_eq!(p.v, 4.0);
+
+        // Ranges can run backwards
+        let mut p = Param::new(5.0, 10.0, 0.0);
+        p.set(-5.0);
+        assert_eq!(p.v, 0.0);
+        p.set_normalized(0.25);
+        assert_eq!(p.v, 7.5);
+
+        let mut p = Param::toggle(false);
+        p.set(0.7);
+        assert_eq!(p.v, 1.0);
+        p.reset();
+        assert_eq!(p.v, 0.0);
+    }
+
+    #[test]
+    fn slew_converges() {
+        let mut p = Param::new(0.0, 0.0, 1.0).slew(100.0);
+
+        p.set_normalized(1.0);
+        assert_eq!(p.v, 0.0);
+        assert_eq!(p.normalized(), 1.0);
+
+        // One time constant in
+        p.update(0.1);
+        assert!((p.v - (1.0 - (-1.0f32).exp())).abs() < 1e-4);
+
+        for _ in 0..100 {
+            p.update(0.016);
+        }
+        assert_eq!(p.v, 1.0);
+
+        // Setting directly cancels the slew
+        p.set_normalized(0.0);
+        p.set(0.5);
+        p.update(1.0);
+        assert_eq!(p.v, 0.5);
+    }
+
+    #[derive(Default)]
+    struct Fx {
+        edge: f32,
+        steps: i32,
+        on: bool,
+        other: f32,
+    }
+
+    crate::bind!(Fx {
+        edge: "fx/edge",
+        steps: "fx/steps",
+        on: "fx/on",
+        other: "missing",
+    });
+
+    #[test]
+    fn binds_fields() {
+        let params = Params::default()
+            .with("fx/edge", Param::new(0.25, 0.0, 1.0))
+            .with("fx/steps", Param::int(7, 0, 16))
+            .with("fx/on", Param::toggle(true));
+
+        let mut fx = Fx {
+            other: 3.0,
+            ..Fx::default()
+        };
+        fx.bind(&params);
+
+        assert_eq!(fx.edge, 0.25);
+        assert_eq!(fx.steps, 7);
+        assert!(fx.on);
+        // Unknown parameters leave the field alone
+        assert_eq!(fx.other, 3.0);
+    }
+}
@@ -1,7 +1,7 @@
 pub use crate::app::{App, Key, KeyState};
 pub use crate::gfx::frame::Frame;
-pub use crate::audio::{Audio, MidiBank, Midi};
-// pub use crate::midi::{Midi, MidiBank, MidiMessage};
+pub use crate::audio::Audio;
+pub use crate::midi::{Midi, MidiBank};
 
 pub use crate::gfx;
 pub use crate::gfx::prelude::*;
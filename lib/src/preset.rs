@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::param::Params;
+use crate::time::Span;
+
+// Presets per bank, one for each button on a row of the controller
+pub const SLOTS: usize = 8;
+
+// Parameter values captured at one moment, a "look"
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub values: HashMap<String, f32>,
+}
+
+impl Snapshot {
+    pub fn capture(params: &Params) -> Self {
+        Self {
+            values: params.iter().map(|(name, p)| (name.to_string(), p.v)).collect(),
+        }
+    }
+
+    // Only the given group, e.g. to keep looks for effects separate from the camera
+    pub fn capture_group(params: &Params, group: &str) -> Self {
+        Self {
+            values: params.group(group).map(|(name, p)| (name.to_string(), p.v)).collect(),
+        }
+    }
+
+    // Parameters that no longer exist are skipped
+    pub fn apply(&self, params: &mut Params) {
+        for (name, &v) in &self.values {
+            if params.contains(name) {
+                params.set(name, v);
+            }
+        }
+    }
+}
+
+struct Morph {
+    from: Snapshot,
+    to: Snapshot,
+    span: Span,
+    // Progress in [0.0, 1.0]
+    f: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Presets {
+    banks: Vec<Vec<Option<Snapshot>>>,
+    #[serde(skip)]
+    bank: usize,
+    #[serde(skip)]
+    morph: Option<Morph>,
+}
+
+impl Presets {
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let mut presets: Self = serde_json::from_slice(&data)?;
+
+        if presets.banks.is_empty() {
+            presets.banks.push(vec![None; SLOTS]);
+        }
+
+        Ok(presets)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data)
+    }
+
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
+    // Switch bank, adding empty ones as needed
+    pub fn select(&mut self, bank: usize) {
+        while self.banks.len() <= bank {
+            self.banks.push(vec![None; SLOTS]);
+        }
+        self.bank = bank;
+    }
+
+    pub fn get(&self, slot: usize) -> Option<&Snapshot> {
+        self.banks[self.bank].get(slot).and_then(Option::as_ref)
+    }
+
+    pub fn store(&mut self, slot: usize, snapshot: Snapshot) {
+        match self.banks[self.bank].get_mut(slot) {
+            Some(s) => *s = Some(snapshot),
+            None => log::warn!("No preset slot {}", slot),
+        }
+    }
+
+    pub fn capture(&mut self, slot: usize, params: &Params) {
+        self.store(slot, Snapshot::capture(params));
+    }
+
+    pub fn clear(&mut self, slot: usize) {
+        if let Some(s) = self.banks[self.bank].get_mut(slot) {
+            *s = None;
+        }
+    }
+
+    // Jump straight to a preset, cancelling any morph. Returns false for an empty slot
+    pub fn recall(&mut self, slot: usize, params: &mut Params) -> bool {
+        match self.get(slot) {
+            Some(snapshot) => {
+                snapshot.apply(params);
+                self.morph = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Glide from the current values to a preset over `span`. Returns false for an empty slot
+    pub fn morph(&mut self, slot: usize, span: Span, params: &Params) -> bool {
+        let to = match self.get(slot) {
+            Some(snapshot) => snapshot.clone(),
+            None => return false,
+        };
+
+        self.morph = Some(Morph {
+            from: Snapshot::capture(params),
+            to,
+            span,
+            f: 0.0,
+        });
+
+        true
+    }
+
+    pub fn morphing(&self) -> bool {
+        self.morph.is_some()
+    }
+
+    // Advance a morph. Beat spans follow `bpm` as it changes
+    pub fn update(&mut self, delta: f32, bpm: f32, params: &mut Params) {
+        let morph = match &mut self.morph {
+            Some(morph) => morph,
+            None => return,
+        };
+
+        let len = morph.span.seconds(bpm);
+        morph.f = if len > 0.0 { (morph.f + delta / len).min(1.0) } else { 1.0 };
+
+        for (name, &to) in &morph.to.values {
+            if let Some(&from) = morph.from.values.get(name) {
+                if params.contains(name) {
+                    params.set(name, from + (to - from) * morph.f);
+                }
+            }
+        }
+
+        if morph.f >= 1.0 {
+            self.morph = None;
+        }
+    }
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Self {
+            banks: vec![vec![None; SLOTS]],
+            bank: 0,
+            morph: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::param::Param;
+
+    fn params() -> Params {
+        Params::default()
+            .with("edge", Param::new(0.0, 0.0, 10.0).group("fx"))
+            .with("steps", Param::int(0, 0, 8).group("fx"))
+            .with("zoom", Param::new(1.0, 0.0, 4.0))
+    }
+
+    // Presets with a look in slot 0
+    fn presets() -> Presets {
+        let mut look = params();
+        look.set("edge", 10.0);
+        look.set("steps", 8.0);
+        look.set("zoom", 3.0);
+
+        let mut presets = Presets::default();
+        presets.capture(0, &look);
+        presets
+    }
+
+    #[test]
+    fn morph_over_seconds() {
+        let mut presets = presets();
+        let mut params = params();
+
+        assert!(presets.morph(0, Span::Seconds(2.0), &params));
+        presets.update(0.5, 120.0, &mut params);
+        assert_eq!(params.f32("edge"), 2.5);
+        // Ints stay whole on the way
+        assert_eq!(params.f32("steps"), 2.0);
+
+        presets.update(0.5, 120.0, &mut params);
+        assert_eq!(params.f32("edge"), 5.0);
+        assert_eq!(params.f32("zoom"), 2.0);
+        assert!(presets.morphing());
+
+        presets.update(5.0, 120.0, &mut params);
+        assert_eq!(params.f32("edge"), 10.0);
+        assert!(!presets.morphing());
+    }
+
+    #[test]
+    fn morph_follows_tempo() {
+        let mut presets = presets();
+        let mut params = params();
+
+        // Four beats are two seconds at 120 BPM, then four at 60
+        presets.morph(0, Span::Beats(4.0), &params);
+        presets.update(0.5, 120.0, &mut params);
+        assert_eq!(params.f32("edge"), 2.5);
+        presets.update(1.0, 60.0, &mut params);
+        assert_eq!(params.f32("edge"), 5.0);
+
+        // Zero length jumps straight there
+        presets.morph(0, Span::Seconds(0.0), &params);
+        presets.update(0.0, 120.0, &mut params);
+        assert_eq!(params.f32("edge"), 10.0);
+        assert!(!presets.morphing());
+    }
+
+    #[test]
+    fn recall_cancels_morph() {
+        let mut presets = presets();
+        let mut params = params();
+
+        assert!(!presets.morph(1, Span::Seconds(1.0), &params));
+        assert!(!presets.morphing());
+
+        presets.morph(0, Span::Seconds(1.0), &params);
+        presets.update(0.5, 120.0, &mut params);
+        params.set("edge", 0.0);
+        assert!(presets.recall(0, &mut params));
+        assert!(!presets.morphing());
+        assert_eq!(params.f32("edge"), 10.0);
+
+        presets.update(0.5, 120.0, &mut params);
+        assert_eq!(params.f32("edge"), 10.0);
+    }
+
+    #[test]
+    fn morph_skips_missing_params() {
+        let mut presets = Presets::default();
+        let mut look = params();
+        look.set("edge", 10.0);
+        presets.store(0, Snapshot::capture_group(&look, "fx"));
+
+        // Added after the morph started, so there's nothing to glide from
+        let mut params = Params::default().with("edge", Param::new(0.0, 0.0, 10.0));
+        presets.morph(0, Span::Seconds(1.0), &params);
+        params.insert("steps", Param::int(4, 0, 8));
+        params.insert("zoom", Param::new(1.0, 0.0, 4.0));
+
+        presets.update(1.0, 120.0, &mut params);
+        assert_eq!(params.f32("edge"), 10.0);
+        assert_eq!(params.f32("steps"), 4.0);
+        assert_eq!(params.f32("zoom"), 1.0);
+    }
+}
@@ -27,8 +27,10 @@ pub fn resource(file: &str) -> PathBuf {
             "png" => "textures",
             "jpg" => "textures",
             "tga" => "textures",
-            "pkf" => "keyframes",
+            "ckf" => "keyframes",
             "glb" => "scenes",
+            "mid" => "midi",
+            "cue" => "timelines",
             ext => panic!("Unable to load format .{}!", ext),
         },
         None => panic!("Unable to determine resource type!"),
@@ -88,6 +90,14 @@ pub fn read_scene(device: &wgpu::Device, file: &str) -> crate::gfx::scene::Scene
     crate::gfx::scene::Scene::new(device, gltf)
 }
 
+pub fn read_smf(file: &str) -> crate::midi::smf::Smf {
+    crate::midi::smf::parse(&read(file)).unwrap()
+}
+
+pub fn read_timeline(file: &str) -> crate::timeline::Timeline {
+    crate::timeline::Timeline::load(resource(file)).unwrap()
+}
+
 pub fn read_font(file: &str) -> wgpu_glyph::ab_glyph::FontArc {
     wgpu_glyph::ab_glyph::FontArc::try_from_vec(read(file)).unwrap()
 }
@@ -1,6 +1,11 @@
+use serde::{Deserialize, Serialize};
+
 use crate::audio::Audio;
+use crate::envelope::Envelope;
+use crate::midi::Midi;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
 
 pub struct Decay {
     c: u32,
@@ -39,36 +44,92 @@ impl Decay {
     }
 }
 
+// Smooths toward a target with a time constant of `t` ms
+pub struct Slew {
+    pub t: f32,
+    v: f32,
+    target: f32,
+}
+
+impl Slew {
+    pub fn new(t: f32, v: f32) -> Self {
+        Self { t, v, target: v }
+    }
+
+    pub fn v(&self) -> f32 {
+        self.v
+    }
+
+    pub fn target(&self) -> f32 {
+        self.target
+    }
+
+    pub fn set(&mut self, target: f32) {
+        self.target = target;
+    }
+
+    // Skip straight to a value without smoothing
+    pub fn jump(&mut self, v: f32) {
+        self.v = v;
+        self.target = v;
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        if self.t <= 0.0 {
+            self.v = self.target;
+        } else {
+            let k = 1.0 - (-(delta * 1000.0) / self.t).exp();
+            self.v += (self.target - self.v) * k;
+        }
+    }
+}
+
+// Named envelopes, plain Decays unless given something else.
+// Unknown names read as off rather than panicking
 pub struct DecayEnv {
-    map: HashMap<&'static str, Decay>,
+    map: HashMap<String, Box<dyn Envelope>>,
 }
 
 impl DecayEnv {
-    pub fn with(mut self, key: &'static str, t: f32) -> Self {
-        self.map.insert(key, Decay::new(t));
+    pub fn with(self, key: &str, t: f32) -> Self {
+        self.with_env(key, Decay::new(t))
+    }
+
+    pub fn with_env<E: Envelope + 'static>(mut self, key: &str, env: E) -> Self {
+        self.map.insert(key.to_string(), Box::new(env));
         self
     }
 
-    pub fn v(&self, key: &'static str) -> f32 {
-        self.map.get(key).unwrap().v()
+    pub fn v(&self, key: &str) -> f32 {
+        self.map.get(key).map_or(0.0, |env| env.v())
     }
 
-    pub fn t(&mut self, key: &'static str, t: f32) {
-        self.map.get_mut(key).unwrap().t = t;
+    pub fn t(&mut self, key: &str, t: f32) {
+        match self.map.get_mut(key) {
+            Some(env) => env.t(t),
+            None => log::warn!("Unknown envelope '{}'", key),
+        }
     }
 
     pub fn update(&mut self, delta: f32) {
-        self.map.values_mut().for_each(|decay| {
-            decay.update(delta);
+        self.map.values_mut().for_each(|env| {
+            env.update(delta);
         });
     }
 
-    pub fn set(&mut self, key: &'static str) {
-        self.map.get_mut(key).unwrap().set();
+    pub fn set(&mut self, key: &str) {
+        match self.map.get_mut(key) {
+            Some(env) => env.trigger(),
+            None => log::warn!("Unknown envelope '{}'", key),
+        }
+    }
+
+    pub fn off(&self, key: &str) -> bool {
+        self.map.get(key).map_or(true, |env| env.off())
     }
 
-    pub fn off(&self, key: &'static str) -> bool {
-        self.map.get(key).unwrap().off()
+    pub fn contains(&self, key: &str) -> bool {
+        self.map.contains_key(key)
     }
 }
 
@@ -80,6 +141,22 @@ impl Default for DecayEnv {
     }
 }
 
+// A length of time in seconds, or in beats that follow the tempo
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Span {
+    Seconds(f32),
+    Beats(f32),
+}
+
+impl Span {
+    pub fn seconds(&self, bpm: f32) -> f32 {
+        match *self {
+            Span::Seconds(s) => s,
+            Span::Beats(b) => b * convert::bpm_ms(bpm) / 1000.0,
+        }
+    }
+}
+
 pub struct BeatClock {
     pub bpm: f32,
     pub mul: f32,
@@ -110,6 +187,216 @@ impl BeatClock {
     pub fn sync(&mut self) {
         self.acc = 0.0;
     }
+
+    // Position within the current (multiplied) beat in [0.0, 1.0)
+    pub fn phase(&self) -> f32 {
+        (self.acc / self.period()).min(1.0)
+    }
+
+    // Lock onto an external tempo and beat position instead of free-running.
+    // Returns true when the external position crosses a beat, like update()
+    pub fn follow(&mut self, bpm: f32, beats: f32) -> bool {
+        self.bpm = bpm;
+
+        let ms = self.period();
+        let acc = (beats * convert::bpm_ms(bpm)).rem_euclid(ms);
+
+        // Only count a wrap if we jumped back by more than half a beat,
+        // so jitter around the boundary doesn't double-trigger
+        let beat = acc < self.acc && self.acc - acc > ms / 2.0;
+        self.acc = acc;
+
+        beat
+    }
+
+    fn period(&self) -> f32 {
+        convert::bpm_ms(self.bpm) * self.mul
+    }
+}
+
+// Estimates tempo from taps on a key or button
+pub struct TapTempo {
+    // Intervals averaged over, in taps
+    pub window: usize,
+    taps: VecDeque<Instant>,
+}
+
+impl TapTempo {
+    // A pause longer than this starts a new tempo
+    const RESET: Duration = Duration::from_secs(2);
+    // Intervals further than this fraction from the median are rejected
+    const TOLERANCE: f32 = 0.2;
+
+    pub fn new(window: usize) -> Self {
+        Self {
+            window: window.max(2),
+            taps: VecDeque::new(),
+        }
+    }
+
+    // Tap now. Returns the estimated BPM once there are enough taps
+    pub fn tap(&mut self) -> Option<f32> {
+        self.tap_at(Instant::now())
+    }
+
+    pub fn tap_at(&mut self, t: Instant) -> Option<f32> {
+        if self.taps.back().map_or(false, |&last| t.saturating_duration_since(last) > Self::RESET) {
+            self.taps.clear();
+        }
+
+        self.taps.push_back(t);
+        while self.taps.len() > self.window + 1 {
+            self.taps.pop_front();
+        }
+
+        self.bpm()
+    }
+
+    // Set the clock's tempo from the taps so far and put the beat on this tap
+    pub fn tap_clock(&mut self, clock: &mut BeatClock) {
+        if let Some(bpm) = self.tap() {
+            clock.bpm = bpm;
+        }
+        clock.sync();
+    }
+
+    pub fn bpm(&self) -> Option<f32> {
+        let mut intervals = self
+            .taps
+            .iter()
+            .zip(self.taps.iter().skip(1))
+            .map(|(a, b)| b.saturating_duration_since(*a).as_secs_f32() * 1000.0)
+            .collect::<Vec<_>>();
+
+        if intervals.len() < 2 {
+            return None;
+        }
+
+        // Drop double taps and missed taps, which land far from the median. The lower
+        // median, so one missed tap out of two intervals can't halve the tempo
+        let mut sorted = intervals.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = sorted[(sorted.len() - 1) / 2];
+        intervals.retain(|i| (i - median).abs() <= median * Self::TOLERANCE);
+
+        // Wait until at least two intervals agree
+        if intervals.len() < 2 {
+            return None;
+        }
+
+        let mean = intervals.iter().sum::<f32>() / intervals.len() as f32;
+        Some(convert::ms_bpm(mean))
+    }
+
+    pub fn reset(&mut self) {
+        self.taps.clear();
+    }
+}
+
+impl Default for TapTempo {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+pub struct MidiClock {
+    intervals: VecDeque<u64>,
+    last: Option<u64>,
+    seen: Option<Instant>,
+    ticks: i64,
+    running: bool,
+}
+
+impl MidiClock {
+    // MIDI clock is sent at 24 pulses per quarter note
+    const PPQN: usize = 24;
+    // Gaps longer than this (in us) restart the tempo estimate
+    const GAP: u64 = 250_000;
+    // Consider the clock gone if no ticks arrived for this long
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    // Feed a decoded message along with its timestamp in microseconds
+    pub fn midi(&mut self, stamp: u64, msg: Midi) {
+        match msg {
+            Midi::Clock => self.tick(stamp),
+            Midi::Start => self.start(),
+            Midi::Continue => self.resume(),
+            Midi::Stop => self.stop(),
+            _ => {}
+        }
+    }
+
+    pub fn tick(&mut self, stamp: u64) {
+        if let Some(last) = self.last {
+            let dt = stamp.saturating_sub(last);
+
+            if dt > Self::GAP {
+                self.intervals.clear();
+            } else {
+                self.intervals.push_back(dt);
+                if self.intervals.len() > Self::PPQN {
+                    self.intervals.pop_front();
+                }
+            }
+        }
+
+        self.last = Some(stamp);
+        self.seen = Some(Instant::now());
+
+        if self.running {
+            self.ticks += 1;
+        }
+    }
+
+    pub fn start(&mut self) {
+        // The first tick after a start message lands on beat 0
+        self.ticks = -1;
+        self.running = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    // Tempo averaged over the last beat of ticks, if a clock is being received
+    pub fn bpm(&self) -> Option<f32> {
+        let alive = self.seen.map_or(false, |t| t.elapsed() < Self::TIMEOUT);
+        if !alive || self.intervals.len() < Self::PPQN / 4 {
+            return None;
+        }
+
+        let avg = self.intervals.iter().sum::<u64>() as f32 / self.intervals.len() as f32;
+        Some(convert::ms_bpm(avg * Self::PPQN as f32 / 1000.0))
+    }
+
+    // Beats elapsed since the last start message, at tick resolution
+    pub fn beats(&self) -> f32 {
+        self.ticks.max(0) as f32 / Self::PPQN as f32
+    }
+
+    pub fn phase(&self) -> f32 {
+        self.beats().fract()
+    }
+
+    pub fn running(&self) -> bool {
+        self.running
+    }
+}
+
+impl Default for MidiClock {
+    fn default() -> Self {
+        Self {
+            intervals: VecDeque::with_capacity(Self::PPQN + 1),
+            last: None,
+            seen: None,
+            ticks: 0,
+            running: false,
+        }
+    }
 }
 
 pub struct BeatDetect {
@@ -162,4 +449,132 @@ pub mod convert {
 
     // pub fn ms_fps(ms: f32) -> f32 {
     // }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 120 BPM, 24 ticks per beat
+    const TICK: u64 = 20_833;
+
+    fn ticks(clock: &mut MidiClock, from: u64, n: u64) -> u64 {
+        for i in 0..n {
+            clock.tick(from + i * TICK);
+        }
+        from + n * TICK
+    }
+
+    fn taps(tap: &mut TapTempo, start: Instant, ms: &[u64]) -> Option<f32> {
+        ms.iter().map(|&ms| tap.tap_at(start + Duration::from_millis(ms))).last().flatten()
+    }
+
+    fn near(bpm: Option<f32>, expected: f32) -> bool {
+        bpm.map_or(false, |bpm| (bpm - expected).abs() < 0.01)
+    }
+
+    #[test]
+    fn tap_steady() {
+        let mut tap = TapTempo::default();
+        let start = Instant::now();
+
+        assert_eq!(taps(&mut tap, start, &[0, 500]), None);
+        assert!(near(taps(&mut tap, start, &[1000]), 120.0));
+        assert!(near(taps(&mut tap, start, &[1500, 2000]), 120.0));
+    }
+
+    #[test]
+    fn tap_double() {
+        let mut tap = TapTempo::default();
+        let start = Instant::now();
+
+        assert!(near(taps(&mut tap, start, &[0, 500, 550, 1050, 1550]), 120.0));
+    }
+
+    #[test]
+    fn tap_missed() {
+        let mut tap = TapTempo::default();
+        let start = Instant::now();
+
+        // One interval each way isn't enough to tell which one is the beat
+        assert_eq!(taps(&mut tap, start, &[0, 500, 1500]), None);
+        assert!(near(taps(&mut tap, start, &[2000]), 120.0));
+    }
+
+    #[test]
+    fn tap_resets_after_pause() {
+        let mut tap = TapTempo::default();
+        let start = Instant::now();
+
+        assert!(near(taps(&mut tap, start, &[0, 500, 1000]), 120.0));
+
+        // A new tempo after a pause, without the old taps
+        assert_eq!(taps(&mut tap, start, &[3500, 4500]), None);
+        assert!(near(taps(&mut tap, start, &[5500]), 60.0));
+    }
+
+    #[test]
+    fn midi_clock_bpm() {
+        let mut clock = MidiClock::default();
+        assert_eq!(clock.bpm(), None);
+
+        ticks(&mut clock, 0, 48);
+        let bpm = clock.bpm().unwrap();
+        assert!((bpm - 120.0).abs() < 0.1, "{}", bpm);
+    }
+
+    #[test]
+    fn midi_clock_gap_restarts_estimate() {
+        let mut clock = MidiClock::default();
+        let t = ticks(&mut clock, 0, 48);
+        clock.tick(t + 1_000_000);
+        assert_eq!(clock.bpm(), None);
+    }
+
+    #[test]
+    fn midi_clock_transport() {
+        let mut clock = MidiClock::default();
+
+        // Clock without transport doesn't move the beat position
+        let t = ticks(&mut clock, 0, 24);
+        assert!(!clock.running());
+        assert_eq!(clock.beats(), 0.0);
+
+        clock.midi(t, Midi::Start);
+        let t = ticks(&mut clock, t, 1);
+        assert!(clock.running());
+        assert_eq!(clock.beats(), 0.0);
+
+        let t = ticks(&mut clock, t, 24);
+        assert_eq!(clock.beats(), 1.0);
+
+        clock.midi(t, Midi::Stop);
+        let t = ticks(&mut clock, t, 12);
+        assert!(!clock.running());
+        assert_eq!(clock.beats(), 1.0);
+
+        clock.midi(t, Midi::Continue);
+        let t = ticks(&mut clock, t, 12);
+        assert_eq!(clock.beats(), 1.5);
+
+        // Start goes back to beat 0
+        clock.midi(t, Midi::Start);
+        clock.tick(t);
+        assert_eq!(clock.beats(), 0.0);
+    }
+
+    #[test]
+    fn follow_wraps_once_per_beat() {
+        let mut clock = BeatClock::new(120.0);
+
+        assert!(!clock.follow(120.0, 0.5));
+        assert!(!clock.follow(120.0, 0.9));
+        assert!(clock.follow(120.0, 1.05));
+        // Jitter back across the boundary isn't another beat
+        assert!(!clock.follow(120.0, 1.04));
+        assert!(!clock.follow(120.0, 1.5));
+        assert!(!clock.follow(120.0, 1.9));
+        assert!(clock.follow(120.0, 2.0));
+        assert!((clock.phase() - 0.0).abs() < 1e-4);
+    }
+}
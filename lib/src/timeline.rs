@@ -0,0 +1,459 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::gfx::animation::Animator;
+use crate::param::Params;
+use crate::time::{BeatClock, Span};
+
+// What cue times are measured in
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Unit {
+    Seconds,
+    // Beats of the BeatClock, following its tempo
+    Beats,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Action {
+    Set { param: String, v: f32 },
+    // Glide from the current value to `v`
+    Ramp { param: String, v: f32, span: Span },
+    // Animator::play on a named animation
+    Play { animation: String, looping: bool },
+    // Handed back from update() for the sketch to act on
+    Scene(String),
+    Toggle { effect: String, on: bool },
+    // Continue from a marker
+    Jump(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cue {
+    pub at: f32,
+    pub action: Action,
+}
+
+// Cues the sketch handles itself
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Scene(String),
+    Toggle(String, bool),
+}
+
+// What cues act on
+pub struct Targets<'a> {
+    pub params: &'a mut Params,
+    pub animator: Option<&'a mut Animator>,
+    // Animation time, as passed to Animator::update
+    pub t: f32,
+}
+
+struct Ramp {
+    param: String,
+    from: f32,
+    to: f32,
+    span: Span,
+    f: f32,
+}
+
+// A pre-programmed show section: cues against wall or musical time, with markers
+// to jump between and an optional loop region, saved as JSON
+#[derive(Serialize, Deserialize)]
+pub struct Timeline {
+    pub unit: Unit,
+    cues: Vec<Cue>,
+    #[serde(default)]
+    markers: HashMap<String, f32>,
+    // Start and end, in the timeline's unit
+    #[serde(default)]
+    pub looping: Option<(f32, f32)>,
+    #[serde(skip)]
+    pos: f32,
+    // Next cue to fire
+    #[serde(skip)]
+    i: usize,
+    #[serde(skip)]
+    playing: bool,
+    #[serde(skip)]
+    ramps: Vec<Ramp>,
+    #[serde(skip)]
+    jump: Option<f32>,
+}
+
+impl Timeline {
+    // Guards against jump cues that land on themselves
+    const MAX_JUMPS: usize = 64;
+
+    pub fn new(unit: Unit) -> Self {
+        Self {
+            unit,
+            cues: Vec::new(),
+            markers: HashMap::new(),
+            looping: None,
+            pos: 0.0,
+            i: 0,
+            playing: false,
+            ramps: Vec::new(),
+            jump: None,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let data = std::fs::read(path)?;
+        let mut timeline: Self = serde_json::from_slice(&data)?;
+        timeline.sort();
+        Ok(timeline)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(self)?;
+        std::fs::write(path, data)
+    }
+
+    pub fn cue(mut self, at: f32, action: Action) -> Self {
+        self.cues.push(Cue { at, action });
+        self.sort();
+        self
+    }
+
+    pub fn marker(mut self, name: &str, at: f32) -> Self {
+        self.markers.insert(name.to_string(), at);
+        self
+    }
+
+    pub fn looping(mut self, start: f32, end: f32) -> Self {
+        self.looping = Some((start, end));
+        self
+    }
+
+    pub fn cues(&self) -> &[Cue] {
+        &self.cues
+    }
+
+    pub fn pos(&self) -> f32 {
+        self.pos
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn play(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) {
+        self.playing = false;
+    }
+
+    // Stop and go back to the start, dropping any ramps in progress
+    pub fn stop(&mut self) {
+        self.playing = false;
+        self.ramps.clear();
+        self.seek(0.0);
+    }
+
+    // Move to a position without firing the cues in between
+    pub fn seek(&mut self, pos: f32) {
+        self.pos = pos;
+        self.i = self.cues.iter().position(|c| c.at >= pos).unwrap_or(self.cues.len());
+    }
+
+    // Continue from a marker on the next update. Returns false if there's no such marker
+    pub fn jump(&mut self, marker: &str) -> bool {
+        match self.markers.get(marker) {
+            Some(&at) => {
+                self.jump = Some(at);
+                true
+            }
+            None => {
+                log::warn!("Unknown marker '{}'", marker);
+                false
+            }
+        }
+    }
+
+    pub fn update(&mut self, delta: f32, clock: &BeatClock, targets: &mut Targets) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        self.ramp(delta, clock.bpm, targets.params);
+
+        if let Some(at) = self.jump.take() {
+            self.seek(at);
+        }
+
+        if !self.playing {
+            return events;
+        }
+
+        let step = match self.unit {
+            Unit::Seconds => delta,
+            Unit::Beats => delta * clock.bpm / 60.0,
+        };
+        let mut end = self.pos + step;
+        let mut jumps = 0;
+
+        'run: loop {
+            // Fire cues up to the end of the loop region, then wrap around
+            let wrap = match self.looping {
+                Some((start, stop)) if stop > start && end >= stop && self.pos < stop => Some((start, stop)),
+                _ => None,
+            };
+            let until = wrap.map_or(end, |(_, stop)| stop);
+
+            while let Some(cue) = self.cues.get(self.i) {
+                if cue.at >= until {
+                    break;
+                }
+
+                let (at, action) = (cue.at, cue.action.clone());
+                self.i += 1;
+                self.fire(action, targets, &mut events);
+
+                // A jump cue moves the playhead, carry on from the marker
+                if let Some(marker) = self.jump.take() {
+                    jumps += 1;
+                    if jumps > Self::MAX_JUMPS {
+                        log::warn!("Timeline jumped {} times in one update, stopping", jumps);
+                        self.playing = false;
+                        return events;
+                    }
+
+                    let rest = end - at;
+                    self.seek(marker);
+                    end = marker + rest;
+                    continue 'run;
+                }
+            }
+
+            match wrap {
+                Some((start, stop)) => {
+                    let rest = end - stop;
+                    self.seek(start);
+                    end = start + rest;
+                }
+                None => break,
+            }
+        }
+
+        self.pos = end;
+
+        if self.looping.is_none() && self.i >= self.cues.len() && self.ramps.is_empty() {
+            self.playing = false;
+        }
+
+        events
+    }
+
+    fn fire(&mut self, action: Action, targets: &mut Targets, events: &mut Vec<Event>) {
+        match action {
+            Action::Set { param, v } => targets.params.set(&param, v),
+            Action::Ramp { param, v, span } => match targets.params.get(&param) {
+                Some(p) => {
+                    let from = p.v;
+                    self.ramps.retain(|r| r.param != param);
+                    self.ramps.push(Ramp {
+                        param,
+                        from,
+                        to: v,
+                        span,
+                        f: 0.0,
+                    });
+                }
+                None => log::warn!("Unknown parameter '{}'", param),
+            },
+            Action::Play { animation, looping } => match targets.animator.as_mut() {
+                Some(animator) => animator.play(targets.t, looping, &animation),
+                None => log::warn!("No animator to play '{}' on", animation),
+            },
+            Action::Scene(scene) => events.push(Event::Scene(scene)),
+            Action::Toggle { effect, on } => events.push(Event::Toggle(effect, on)),
+            Action::Jump(marker) => {
+                self.jump(&marker);
+            }
+        }
+    }
+
+    fn ramp(&mut self, delta: f32, bpm: f32, params: &mut Params) {
+        for ramp in self.ramps.iter_mut() {
+            let len = ramp.span.seconds(bpm);
+            ramp.f = if len > 0.0 { (ramp.f + delta / len).min(1.0) } else { 1.0 };
+            params.set(&ramp.param, ramp.from + (ramp.to - ramp.from) * ramp.f);
+        }
+
+        self.ramps.retain(|r| r.f < 1.0);
+    }
+
+    // Cues at NaN or infinite times could never fire and can't be ordered, so they're dropped
+    fn sort(&mut self) {
+        self.cues.retain(|c| {
+            if !c.at.is_finite() {
+                log::warn!("Dropping cue at {}: {:?}", c.at, c.action);
+            }
+            c.at.is_finite()
+        });
+        self.cues.sort_by(|a, b| a.at.partial_cmp(&b.at).unwrap());
+        self.seek(self.pos);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::param::Param;
+
+    fn scene(name: &str) -> Action {
+        Action::Scene(name.to_string())
+    }
+
+    fn scenes(events: Vec<Event>) -> Vec<String> {
+        events
+            .into_iter()
+            .filter_map(|e| match e {
+                Event::Scene(s) => Some(s),
+                _ => None,
+            })
+            .collect()
+    }
+
+    struct Show {
+        timeline: Timeline,
+        clock: BeatClock,
+        params: Params,
+    }
+
+    impl Show {
+        fn new(mut timeline: Timeline) -> Self {
+            timeline.play();
+            Self {
+                timeline,
+                clock: BeatClock::new(120.0),
+                params: Params::default().with("x", Param::new(0.0, 0.0, 10.0)),
+            }
+        }
+
+        fn update(&mut self, delta: f32) -> Vec<String> {
+            let mut targets = Targets {
+                params: &mut self.params,
+                animator: None,
+                t: 0.0,
+            };
+            scenes(self.timeline.update(delta, &self.clock, &mut targets))
+        }
+    }
+
+    #[test]
+    fn drops_non_finite_cues() {
+        let timeline = Timeline::new(Unit::Seconds)
+            .cue(f32::NAN, scene("nan"))
+            .cue(2.0, scene("b"))
+            .cue(f32::INFINITY, scene("inf"))
+            .cue(1.0, scene("a"));
+
+        let at = timeline.cues().iter().map(|c| c.at).collect::<Vec<_>>();
+        assert_eq!(at, vec![1.0, 2.0]);
+    }
+
+    #[test]
+    fn loops() {
+        let mut show = Show::new(
+            Timeline::new(Unit::Seconds)
+                .cue(0.5, scene("a"))
+                .cue(1.5, scene("b"))
+                .cue(3.0, scene("never"))
+                .looping(0.0, 2.0),
+        );
+
+        assert_eq!(show.update(1.0), vec!["a"]);
+        assert_eq!(show.update(1.0), vec!["b"]);
+        assert_eq!(show.timeline.pos(), 0.0);
+
+        // Both cues in one update that crosses the loop end
+        assert_eq!(show.update(1.0), vec!["a"]);
+        assert_eq!(show.update(1.75), vec!["b", "a"]);
+        assert_eq!(show.timeline.pos(), 0.75);
+        assert!(show.timeline.playing());
+    }
+
+    #[test]
+    fn jumps() {
+        let mut show = Show::new(
+            Timeline::new(Unit::Seconds)
+                .marker("verse", 10.0)
+                .cue(1.0, Action::Jump("verse".to_string()))
+                .cue(5.0, scene("skipped"))
+                .cue(10.5, scene("verse")),
+        );
+
+        // The rest of the update carries on from the marker
+        assert_eq!(show.update(2.0), vec!["verse"]);
+        assert_eq!(show.timeline.pos(), 11.0);
+
+        assert!(!show.timeline.jump("chorus"));
+        assert!(show.timeline.jump("verse"));
+        show.timeline.play();
+        show.update(0.0);
+        assert_eq!(show.timeline.pos(), 10.0);
+    }
+
+    #[test]
+    fn jump_loops_stop() {
+        let jump = Action::Jump("a".to_string());
+        let mut show = Show::new(Timeline::new(Unit::Seconds).marker("a", 0.0).cue(0.0, jump));
+
+        show.update(0.1);
+        assert!(!show.timeline.playing());
+    }
+
+    #[test]
+    fn ramps() {
+        let ramp = |span| Action::Ramp {
+            param: "x".to_string(),
+            v: 10.0,
+            span,
+        };
+
+        for &span in &[Span::Seconds(1.0), Span::Beats(2.0)] {
+            let mut show = Show::new(Timeline::new(Unit::Seconds).cue(0.0, ramp(span)));
+
+            show.update(0.1);
+            assert_eq!(show.params.v("x"), 0.0);
+            show.update(0.5);
+            assert!((show.params.v("x") - 5.0).abs() < 1e-4);
+            assert!(show.timeline.playing());
+            show.update(0.5);
+            assert_eq!(show.params.v("x"), 10.0);
+            assert!(!show.timeline.playing());
+        }
+    }
+
+    #[test]
+    fn load_json() {
+        let path = std::env::temp_dir().join(format!("timeline-{}.json", std::process::id()));
+        let json = r#"{
+            "unit": "Beats",
+            "cues": [
+                { "at": 4.0, "action": { "Scene": "b" } },
+                { "at": 1.0, "action": { "Set": { "param": "x", "v": 3.0 } } },
+                { "at": 2.0, "action": { "Scene": "a" } }
+            ],
+            "markers": { "drop": 8.0 }
+        }"#;
+        std::fs::write(&path, json).unwrap();
+
+        let timeline = Timeline::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let at = timeline.cues().iter().map(|c| c.at).collect::<Vec<_>>();
+        assert_eq!(at, vec![1.0, 2.0, 4.0]);
+        assert_eq!(timeline.looping, None);
+
+        // Half a second is a beat at 120 BPM
+        let mut show = Show::new(timeline);
+        assert_eq!(show.update(1.25), vec!["a"]);
+        assert_eq!(show.params.v("x"), 3.0);
+        assert!(show.timeline.jump("drop"));
+    }
+}
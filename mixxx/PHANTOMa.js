@@ -0,0 +1,106 @@
+// Mirrors deck state out as MIDI for PHANTOMa, in the layout lib/src/osc/mixxx.rs decodes.
+// Deck d uses CCs from 16 + 8d:
+//   +1 BPM low, +2 BPM hundredths, +3 BPM high (bpm = 127 * high + low + 0.01 * hundredths)
+//   +4 position coarse, +5 position fine (14 bit, fraction of the track)
+//   +6 play state, +7 track counter, bumped whenever a track is loaded
+// Beats are note on 50 + d, the crossfader is CC 15.
+
+var PHANTOMa = {};
+
+PHANTOMa.DECKS = 4;
+PHANTOMa.CROSSFADER = 15;
+PHANTOMa.BEAT = 50;
+
+PHANTOMa.connections = [];
+PHANTOMa.tracks = [];
+PHANTOMa.positions = [];
+
+PHANTOMa.cc = function (cc, value) {
+    midi.sendShortMsg(0xB0, cc, Math.max(0, Math.min(127, Math.round(value))));
+};
+
+PHANTOMa.base = function (deck) {
+    return 16 + 8 * deck;
+};
+
+PHANTOMa.bpm = function (deck, bpm) {
+    var whole = Math.floor(bpm);
+    var hundredths = Math.round((bpm - whole) * 100);
+    if (hundredths === 100) {
+        whole += 1;
+        hundredths = 0;
+    }
+
+    var high = Math.floor(whole / 127);
+    var base = PHANTOMa.base(deck);
+
+    // High goes last, it completes the value on the other end
+    PHANTOMa.cc(base + 1, whole - 127 * high);
+    PHANTOMa.cc(base + 2, hundredths);
+    PHANTOMa.cc(base + 3, high);
+};
+
+PHANTOMa.position = function (deck, position) {
+    var v = Math.round(Math.max(0, Math.min(1, position)) * 16383);
+
+    // playposition updates every audio callback, only send what the 14 bits can show
+    if (v === PHANTOMa.positions[deck]) {
+        return;
+    }
+    PHANTOMa.positions[deck] = v;
+
+    var base = PHANTOMa.base(deck);
+    PHANTOMa.cc(base + 4, v >> 7);
+    PHANTOMa.cc(base + 5, v & 0x7F);
+};
+
+PHANTOMa.connect = function (group, key, callback) {
+    var connection = engine.makeConnection(group, key, callback);
+    connection.trigger();
+    PHANTOMa.connections.push(connection);
+};
+
+PHANTOMa.init = function () {
+    for (var d = 0; d < PHANTOMa.DECKS; d++) {
+        var group = "[Channel" + (d + 1) + "]";
+        PHANTOMa.tracks[d] = 0;
+
+        (function (deck) {
+            PHANTOMa.connect(group, "bpm", function (value) {
+                PHANTOMa.bpm(deck, value);
+            });
+
+            PHANTOMa.connect(group, "playposition", function (value) {
+                PHANTOMa.position(deck, value);
+            });
+
+            PHANTOMa.connect(group, "play", function (value) {
+                PHANTOMa.cc(PHANTOMa.base(deck) + 6, value ? 127 : 0);
+            });
+
+            PHANTOMa.connect(group, "track_loaded", function (value) {
+                if (value) {
+                    PHANTOMa.tracks[deck] = (PHANTOMa.tracks[deck] + 1) % 128;
+                }
+                PHANTOMa.cc(PHANTOMa.base(deck) + 7, PHANTOMa.tracks[deck]);
+            });
+
+            PHANTOMa.connect(group, "beat_active", function (value) {
+                if (value) {
+                    midi.sendShortMsg(0x90, PHANTOMa.BEAT + deck, 127);
+                }
+            });
+        })(d);
+    }
+
+    PHANTOMa.connect("[Master]", "crossfader", function (value) {
+        PHANTOMa.cc(PHANTOMa.CROSSFADER, (value + 1) * 63.5);
+    });
+};
+
+PHANTOMa.shutdown = function () {
+    PHANTOMa.connections.forEach(function (connection) {
+        connection.disconnect();
+    });
+    PHANTOMa.connections = [];
+};
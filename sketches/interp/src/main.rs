@@ -34,7 +34,7 @@ fn view(app: &App, _model: &Model, frame: Frame) {
 
     let pd1 = 4.0;
     let t1 = pd1 - ((t % (2.0 * pd1)) - pd1).abs();
-    let pos1 = spline1.sample(t1).unwrap();
+    let pos1 = interp::sample(&spline1, t1).unwrap();
     draw.ellipse().color(RED).x_y(pos1.x, pos1.y).radius(3.0);
 
 
@@ -54,7 +54,7 @@ fn view(app: &App, _model: &Model, frame: Frame) {
 
     let pd2 = 10.0;
     let t2 = t % pd2;
-    let pos2 = spline2.sample(t2).unwrap();
+    let pos2 = interp::sample(&spline2, t2).unwrap();
     draw.ellipse().color(RED).x_y(pos2.x, pos2.y).radius(3.0);
 
 
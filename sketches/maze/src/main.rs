@@ -15,7 +15,7 @@ use lib::{
     audio::{self, Audio},
     gfx::{Composite, Drawer, Effect, Present},
     midi::{Midi, MidiMessage, MidiBank},
-    osc::{Osc, OscMessage},
+    osc::{MixxxEvent, Osc, OscMessage},
     time::{BeatClock, BeatDetect, DecayEnv},
     twitch::TwitchBuffer,
 };
@@ -321,6 +321,16 @@ fn update(app: &App, model: &mut AppModel, update: Update) {
     for msg in model.osc.poll() {
         match msg {
             OscMessage::Bpm(bpm) => model.beat_clock.bpm = bpm,
+            // Move on to the next scene whenever the mix crosses over to another deck
+            OscMessage::Mixxx(MixxxEvent::Lead(Some(_))) => {
+                model.effect_state.glitch = 0.0;
+                model.param.index = (model.param.index + 1).mod_floor(&12);
+                model.decay.set("flash");
+            }
+            // Hint at the next track as it's loaded
+            OscMessage::Mixxx(MixxxEvent::TrackChange(deck)) if Some(deck) != model.osc.mixxx.lead() => {
+                model.decay.set("edge");
+            }
             _ => {}
         }
     }
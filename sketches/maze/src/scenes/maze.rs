@@ -68,8 +68,8 @@ impl Maze {
     }
 
     pub fn update(&mut self, t: f32) {
-        let curr = self.path.sample(t % 25.0).unwrap();
-        let next = self.path.sample((t + 0.1) % 25.0).unwrap();
+        let curr = interp::sample(&self.path, t % 25.0).unwrap();
+        let next = interp::sample(&self.path, (t + 0.1) % 25.0).unwrap();
 
         let cam = &mut self.scene.camera.desc;
         cam.pos = Vector3::new(curr.x, 0.75, curr.y);
@@ -1,5 +1,6 @@
-use lib::time::{BeatClock, BeatDetect};
-use lib::audio::{Audio, Midi};
+use lib::time::{BeatClock, BeatDetect, TapTempo};
+use lib::audio::Audio;
+use lib::midi::{Device, Feedback, Midi, MidiBank};
 
 pub enum BeatSource {
     Detect,
@@ -9,6 +10,7 @@ pub enum BeatSource {
 pub struct Beat {
     detect: BeatDetect,
     clock: BeatClock,
+    tap: TapTempo,
     source: BeatSource,
     active: bool,
     manual: bool,
@@ -20,8 +22,8 @@ impl Beat {
             // Hold or toggle active with two bank buttons
             Midi::BankButton(_, b) => self.active = b,
 
-            // Clock multipliers, sync, and source control
-            Midi::CtrlButton(0, true) => self.clock.sync(),
+            // Tap tempo, clock multipliers, and source control
+            Midi::CtrlButton(0, true) => self.tap(),
             Midi::CtrlButton(1, true) => self.clock.mul = 2.0,
             Midi::CtrlButton(2, true) => self.clock.mul = 1.0,
             Midi::CtrlButton(3, true) => self.clock.mul = 0.5,
@@ -37,9 +39,37 @@ impl Beat {
         }
     }
 
-    pub fn update(&mut self, dt: f32, audio: &mut Audio) -> bool {
+    // Set the clock's tempo and phase from taps
+    pub fn tap(&mut self) {
+        self.tap.tap_clock(&mut self.clock);
+    }
+
+    // Show the active source and clock multiplier on the control buttons
+    pub fn feedback(&self, fb: &mut Feedback) {
+        let mul = |m: f32| (self.clock.mul - m).abs() < f32::EPSILON;
+        fb.set(MidiBank::B0, Midi::CtrlButton(1, mul(2.0)));
+        fb.set(MidiBank::B0, Midi::CtrlButton(2, mul(1.0)));
+        fb.set(MidiBank::B0, Midi::CtrlButton(3, mul(0.5)));
+        fb.set(MidiBank::B0, Midi::CtrlButton(4, mul(0.25)));
+        fb.set(
+            MidiBank::B0,
+            Midi::CtrlButton(5, matches!(self.source, BeatSource::Clock)),
+        );
+    }
+
+    pub fn update(&mut self, dt: f32, audio: &Audio, midi: &Device) -> bool {
         let detect = self.detect.update(dt, audio);
-        let clock = self.clock.update(dt);
+
+        // Lock onto an external MIDI clock when one is being received. Without
+        // transport (no Start yet, or stopped) only take its tempo and free-run
+        let clock = match midi.clock.bpm() {
+            Some(bpm) if midi.clock.running() => self.clock.follow(bpm, midi.clock.beats()),
+            Some(bpm) => {
+                self.clock.bpm = bpm;
+                self.clock.update(dt)
+            }
+            None => self.clock.update(dt),
+        };
         let manual = self.manual;
         self.manual = false;
 
@@ -57,6 +87,7 @@ impl Default for Beat {
         Beat {
             detect: BeatDetect::new(40.0, 120.0, 0.005, 400.0),
             clock: BeatClock::new(60.0),
+            tap: TapTempo::default(),
             source: BeatSource::Detect,
             active: true,
             manual: false,
@@ -1,12 +1,13 @@
-use lib::time::Decay as DecayInstance;
+use lib::curve::Ease;
+use lib::envelope::{Adsr, Envelope};
 
 pub struct Decay {
-    beat: DecayInstance,
+    beat: Adsr,
 }
 
 impl Decay {
     pub fn beat_set(&mut self) {
-        self.beat.set();
+        self.beat.trigger();
     }
 
     pub fn beat(&self) -> f32 {
@@ -21,7 +22,7 @@ impl Decay {
 impl Default for Decay {
     fn default() -> Self {
         Self {
-            beat: DecayInstance::new(250.0)
+            beat: Adsr::hit(10.0, 250.0).shapes(Ease::Linear, Ease::OutExp(3.0), Ease::Linear),
         }
     }
-}
\ No newline at end of file
+}
@@ -2,6 +2,7 @@ use gfx::animation::Animator;
 use gfx::pass::{FilterPass, RingPass, TextPass, TextPassBuilder};
 use gfx::scene::Scene;
 use lib::prelude::*;
+use lib::midi::{Device, Feedback};
 use lib::resource;
 
 mod pipeline;
@@ -25,8 +26,10 @@ struct Model {
     t_mul: f32,
 
     audio: Audio,
+    midi: Device,
     beat: Beat,
     decay: Decay,
+    feedback: Feedback,
 
     scene: Scene,
     animator: Animator,
@@ -57,14 +60,18 @@ async fn model(app: &App) -> Model {
     let fx = pipeline::Fx::new(device);
     let ring = RingPass::new(device, 4);
 
+    let audio = Audio::default();
+
     Model {
         tc: 0.0,
         t: 0.0,
         t_mul: 1.0,
 
-        audio: Audio::default(),
+        midi: Device::jack(&audio),
+        audio,
         beat: Beat::default(),
         decay: Decay::default(),
+        feedback: Feedback::default(),
 
         scene,
         animator,
@@ -86,6 +93,7 @@ async fn input(_app: &App, m: &mut Model, state: KeyState, key: Key) {
 
     match key {
         Key::Space => m.decay.beat_set(),
+        Key::T => m.beat.tap(),
         Key::A => m.animator.play(m.t, false, "IcosphereAction"),
         _ => {}
     }
@@ -103,7 +111,7 @@ fn midi(m: &mut Model, _bank: MidiBank, msg: Midi) {
 
 async fn update(_app: &App, m: &mut Model, dt: f32) {
     m.audio.update();
-    m.audio.midi().iter().for_each(|(b, msg)| midi(m, *b, *msg));
+    m.midi.poll().iter().for_each(|(b, msg)| midi(m, *b, *msg));
 
     let dt_mod = dt * (m.t_mul * 200.0) * m.audio.rms();
     m.tc += dt;
@@ -112,11 +120,15 @@ async fn update(_app: &App, m: &mut Model, dt: f32) {
     m.decay.update(dt);
     m.fx.update(m.tc, m.t);
 
-    let beat = m.beat.update(dt, &mut m.audio);
+    let beat = m.beat.update(dt, &m.audio, &m.midi);
     if beat {
         m.decay.beat_set();
+        m.feedback.flash(MidiBank::B0, Midi::MainButton(0, true), 100.0);
     }
 
+    m.beat.feedback(&mut m.feedback);
+    m.feedback.update(dt, &mut m.midi);
+
     m.text.draw(|d| {
         d.at(v2(200.0, 200.0))
             .text("Test", |t| t.scale(62.0).color(v4(1.0, 0.0, 0.0, 1.0)))
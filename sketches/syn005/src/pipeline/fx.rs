@@ -1,4 +1,5 @@
-use lib::audio::Midi;
+use lib::midi::Midi;
+use lib::param::{Bind, Param, Params};
 
 use lib::gfx::frame::Frame;
 use lib::gfx::pass::FilterPass;
@@ -22,7 +23,19 @@ pub struct FxState {
     mega: f32,
 }
 
+lib::bind!(FxState {
+    edge: "fx/edge",
+    glitch: "fx/glitch",
+    vhs: "fx/vhs",
+    pause: "fx/pause",
+    black: "fx/black",
+});
+
+// Sliders 0-4, in order
+const SLIDERS: [&str; 5] = ["fx/edge", "fx/glitch", "fx/vhs", "fx/pause", "fx/black"];
+
 pub struct Fx {
+    pub params: Params,
     state: UniformStorage<FxState>,
 
     edge: FilterPass,
@@ -44,7 +57,12 @@ impl Fx {
         let fade = FilterPass::new(device, "fade", "fade.frag.spv", Some(state.as_ref()));
         let pause = FilterPass::new(device, "pause", "pause.frag.spv", Some(state.as_ref()));
 
+        let params = SLIDERS
+            .iter()
+            .fold(Params::default(), |params, &name| params.with(name, Param::new(0.0, 0.0, 1.0).group("fx")));
+
         Self {
+            params,
             state,
             edge,
             shake,
@@ -58,15 +76,14 @@ impl Fx {
     pub fn update(&mut self, tc: f32, t: f32) {
         self.state.tc = tc;
         self.state.t = t;
+        self.state.bind(&self.params);
     }
 
     pub fn midi(&mut self, msg: Midi) {
         match msg {
-            Midi::Slider(0, f) => self.state.edge = f,
-            Midi::Slider(1, f) => self.state.glitch = f,
-            Midi::Slider(2, f) => self.state.vhs = f,
-            Midi::Slider(3, f) => self.state.pause = f,
-            Midi::Slider(4, f) => self.state.black = f,
+            Midi::Slider(i, f) if (i as usize) < SLIDERS.len() => {
+                self.params.set_normalized(SLIDERS[i as usize], f)
+            }
             _ => {},
         }
     }